@@ -0,0 +1,15 @@
+-- Loaded at start, along with every other
+-- script in this directory. See src/script.rs
+-- for what the game table offers.
+
+-- Walks around at random for a while. Try
+-- `behave <werf> wander` in the console.
+game.behaviour("wander", function(werf, dt)
+    werf.vx = werf.vx + (math.random() - 0.5) * dt * 8
+    werf.vy = werf.vy + (math.random() - 0.5) * dt * 8
+    return math.random() < 0.005
+end)
+
+game.on("dug", function(e)
+    game.log("dug out " .. e.tile .. " at " .. e.x .. "," .. e.y)
+end)
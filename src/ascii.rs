@@ -0,0 +1,91 @@
+use hecs::World;
+
+use crate::{
+    entities::{Position, State},
+    tiles::Tiles,
+};
+
+const WERF: char = '@';
+const PATH: char = '*';
+// For tiles without a glyph.
+const UNKNOWN: char = '?';
+
+// One character per cell of level z, with the
+// remaining paths of moving werfs and the
+// werfs themselves on top.
+pub fn render(tiles: &Tiles, world: &World, z: usize) -> String {
+    let level_len = tiles.width * tiles.height;
+    let mut cells = (z * level_len..(z + 1) * level_len)
+        .map(|i| match tiles.top_tile(i) {
+            Some(t) => tiles.registry.get(t).glyph.unwrap_or(UNKNOWN),
+            None => ' ',
+        })
+        .collect::<Vec<_>>();
+
+    for (_id, state) in world.query::<&State>().iter() {
+        let State::Moving(moving) = state else {
+            continue;
+        };
+        for index in moving.remaining() {
+            let i = index.0 as usize;
+            if i / level_len == z {
+                cells[i % level_len] = PATH;
+            }
+        }
+    }
+
+    for (_id, pos) in world.query::<&Position>().iter() {
+        if pos.z != z {
+            continue;
+        }
+        let index = pos.to_world_index(tiles).0 as usize;
+        if index / level_len == z {
+            cells[index % level_len] = WERF;
+        }
+    }
+
+    let mut out = String::with_capacity(level_len + tiles.height);
+    for row in cells.chunks(tiles.width) {
+        out.extend(row);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::prelude::*;
+
+    use super::*;
+    use crate::{
+        constants::TILE_SIZE,
+        entities::{Velocity, WorldIndex},
+        test_util,
+    };
+
+    #[test]
+    fn tiles_paths_and_werfs_are_drawn() {
+        #[rustfmt::skip]
+        let tiles = test_util::tiles(
+            vec![
+                1, 1, 1, 1, 1,
+                1, 0, 0, 2, 1,
+                1, 1, 1, 1, 1,
+            ],
+            5,
+            3,
+        );
+
+        let mut world = World::new();
+        world.spawn((
+            Position {
+                p: vec2(1.0, 1.0) * TILE_SIZE,
+                z: 0,
+            },
+            Velocity { v: Vec2::ZERO },
+            State::new_moving(vec![WorldIndex(7)]),
+        ));
+
+        assert_eq!(render(&tiles, &world, 0), "#####\n#@*<#\n#####\n");
+    }
+}
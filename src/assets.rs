@@ -0,0 +1,294 @@
+use std::{
+    env,
+    fs::read_to_string,
+    io,
+    path::{Path, PathBuf},
+};
+
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+use crate::{placeholder, tile::TileRegistry};
+
+const MANIFEST_PATH: &str = "data/assets.toml";
+const ROOT_VAR: &str = "WERFS_ASSETS";
+
+pub const WERFS: &str = "werfs";
+pub const TILESET: &str = "tileset";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SheetDef {
+    pub name: String,
+    pub path: String,
+    pub sprite: [u16; 2],
+    pub columns: u8,
+    pub rows: u8,
+}
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    tiles: String,
+    palette: String,
+    autotile: String,
+    level: String,
+    tuning: String,
+    scripts: String,
+    sheet: Vec<SheetDef>,
+}
+
+// Everything needed to find the game's files,
+// without loading any textures. Headless runs
+// only need this.
+#[derive(Debug)]
+pub struct Manifest {
+    pub root: PathBuf,
+    pub tiles: PathBuf,
+    pub palette: PathBuf,
+    pub autotile: PathBuf,
+    pub level: PathBuf,
+    pub tuning: PathBuf,
+    pub scripts: PathBuf,
+    sheets: Vec<SheetDef>,
+}
+
+impl Manifest {
+    // Without a root, WERFS_ASSETS is used, or
+    // else the first directory at or above the
+    // executable that has a manifest, or else
+    // the working directory.
+    pub fn load(root: Option<PathBuf>) -> io::Result<Self> {
+        let root = root
+            .or_else(|| env::var_os(ROOT_VAR).map(PathBuf::from))
+            .or_else(find_root)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        let path = root.join(MANIFEST_PATH);
+        let s = read_to_string(&path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        Self::parse(&s, root)
+    }
+
+    pub fn parse(s: &str, root: PathBuf) -> io::Result<Self> {
+        let file: ManifestFile =
+            toml::from_str(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        for name in [WERFS, TILESET] {
+            if !file.sheet.iter().any(|sheet| sheet.name == name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("asset manifest is missing sheet {}", name),
+                ));
+            }
+        }
+        for sheet in &file.sheet {
+            check_sheet(sheet)?;
+        }
+
+        Ok(Self {
+            tiles: root.join(file.tiles),
+            palette: root.join(file.palette),
+            autotile: root.join(file.autotile),
+            level: root.join(file.level),
+            tuning: root.join(file.tuning),
+            scripts: root.join(file.scripts),
+            sheets: file.sheet,
+            root,
+        })
+    }
+
+    // Names are checked when loading.
+    pub fn sheet(&self, name: &str) -> &SheetDef {
+        self.sheets
+            .iter()
+            .find(|sheet| sheet.name == name)
+            .unwrap_or_else(|| panic!("unknown sheet {}", name))
+    }
+
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+// Placeholders are generated at the full size
+// of the sheet, which has to fit an Image.
+fn check_sheet(def: &SheetDef) -> io::Result<()> {
+    let [w, h] = def.sprite;
+    let size = (
+        w.checked_mul(def.columns as u16),
+        h.checked_mul(def.rows as u16),
+    );
+    match size {
+        (Some(width), Some(height)) if width > 0 && height > 0 => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "sheet {} has a {}x{} grid of {}x{} sprites, which is empty or too large",
+                def.name, def.columns, def.rows, w, h
+            ),
+        )),
+    }
+}
+
+fn find_root() -> Option<PathBuf> {
+    let exe = env::current_exe().ok()?;
+    exe.ancestors()
+        .skip(1)
+        .find(|dir| dir.join(MANIFEST_PATH).exists())
+        .map(Path::to_path_buf)
+}
+
+pub struct Sheet {
+    pub texture: Texture2D,
+    pub def: SheetDef,
+}
+
+impl Sheet {
+    // Sprites are numbered row by row.
+    pub fn source(&self, index: u8) -> Rect {
+        let columns = self.def.columns.max(1);
+        self.source_at(index % columns, index / columns)
+    }
+
+    pub fn source_at(&self, column: u8, row: u8) -> Rect {
+        let [w, h] = self.def.sprite.map(f32::from);
+        Rect::new(column as f32 * w, row as f32 * h, w, h)
+    }
+}
+
+pub struct Assets {
+    pub werfs: Sheet,
+    pub tileset: Sheet,
+}
+
+impl Assets {
+    // Missing textures are replaced by generated
+    // placeholders, as the art is not public.
+    pub async fn load(manifest: &Manifest) -> Self {
+        let werfs = match load_sheet(manifest, WERFS).await {
+            Ok(sheet) => sheet,
+            Err(err) => {
+                eprintln!("warning: {}, using placeholder werfs", err);
+                placeholder_sheet(manifest.sheet(WERFS), placeholder::werfs)
+            }
+        };
+
+        let tileset = match load_sheet(manifest, TILESET).await {
+            Ok(sheet) => sheet,
+            Err(err) => {
+                eprintln!("warning: {}, using placeholder tileset", err);
+                // Only used for colouring, a broken
+                // registry fails properly later on.
+                let def = manifest.sheet(TILESET);
+                let registry = TileRegistry::load(&manifest.tiles, def.columns, def.rows).ok();
+                placeholder_sheet(def, |def| placeholder::tileset(def, registry.as_ref()))
+            }
+        };
+
+        Self { werfs, tileset }
+    }
+
+    // Keeps the current texture if the new one
+    // fails to load, it's probably mid-save.
+    pub async fn reload(&mut self, manifest: &Manifest, name: &str) {
+        let sheet = match name {
+            WERFS => &mut self.werfs,
+            TILESET => &mut self.tileset,
+            _ => return,
+        };
+        match load_sheet(manifest, name).await {
+            Ok(loaded) => *sheet = loaded,
+            Err(err) => eprintln!("warning: {}, keeping the previous {}", err, name),
+        }
+    }
+}
+
+fn placeholder_sheet(def: &SheetDef, generate: impl Fn(&SheetDef) -> Image) -> Sheet {
+    let texture = Texture2D::from_image(&generate(def));
+    texture.set_filter(FilterMode::Nearest);
+    Sheet {
+        texture,
+        def: def.clone(),
+    }
+}
+
+async fn load_sheet(manifest: &Manifest, name: &str) -> io::Result<Sheet> {
+    let def = manifest.sheet(name).clone();
+    let path = manifest.resolve(&def.path);
+    let texture = load_texture(&path.to_string_lossy()).await.map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("failed to load {}: {}", path.display(), err),
+        )
+    })?;
+    texture.set_filter(FilterMode::Nearest);
+
+    // Sprites outside of the texture are drawn
+    // empty, which is easy to miss.
+    let [w, h] = def.sprite.map(f32::from);
+    if texture.width() < def.columns as f32 * w || texture.height() < def.rows as f32 * h {
+        eprintln!(
+            "warning: {} is smaller than its {}x{} grid of {}x{} sprites",
+            path.display(),
+            def.columns,
+            def.rows,
+            w,
+            h
+        );
+    }
+
+    Ok(Sheet { texture, def })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_paths_are_relative_to_the_root() {
+        let manifest = Manifest::load(Some(PathBuf::from("."))).unwrap();
+        assert_eq!(manifest.tiles, Path::new("./data/tiles.toml"));
+        assert_eq!(manifest.sheet(TILESET).columns, 16);
+    }
+
+    #[test]
+    fn manifest_needs_every_sheet() {
+        let s = r#"
+            tiles = "tiles.toml"
+            palette = "palette.toml"
+            autotile = "autotile"
+            level = "level"
+            tuning = "tuning.toml"
+            scripts = "scripts"
+
+            [[sheet]]
+            name = "werfs"
+            path = "werfs.png"
+            sprite = [16, 16]
+            columns = 2
+            rows = 4
+        "#;
+        let err = Manifest::parse(s, PathBuf::new()).expect_err("should fail");
+        assert!(err.to_string().contains("tileset"));
+    }
+
+    #[test]
+    fn sheets_have_to_fit_an_image() {
+        let s = read_to_string("data/assets.toml").unwrap();
+        for (from, to) in [
+            (
+                "sprite = [16, 16]\ncolumns = 2",
+                "sprite = [0, 16]\ncolumns = 2",
+            ),
+            ("columns = 2", "columns = 0"),
+            ("rows = 16", "rows = 0"),
+            (
+                "sprite = [16, 16]\ncolumns = 16",
+                "sprite = [4096, 16]\ncolumns = 16",
+            ),
+        ] {
+            assert!(s.contains(from), "{}", from);
+            let err = Manifest::parse(&s.replace(from, to), PathBuf::new()).expect_err(to);
+            assert!(err.to_string().contains("empty or too large"), "{}", err);
+        }
+    }
+}
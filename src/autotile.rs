@@ -0,0 +1,277 @@
+use std::{fs::read_to_string, io, path::Path};
+
+use ::rand::{rngs::StdRng, Rng};
+
+// Neighbour bits, clockwise from the top.
+// A bit is set when that neighbour is a wall
+// or lies outside of the map.
+pub const N: u8 = 1;
+pub const NE: u8 = 2;
+pub const E: u8 = 4;
+pub const SE: u8 = 8;
+pub const S: u8 = 16;
+pub const SW: u8 = 32;
+pub const W: u8 = 64;
+pub const NW: u8 = 128;
+
+// Offsets matching the bits above.
+pub const NEIGHBOURS: [(i32, i32, u8); 8] = [
+    (0, -1, N),
+    (1, -1, NE),
+    (1, 0, E),
+    (1, 1, SE),
+    (0, 1, S),
+    (-1, 1, SW),
+    (-1, 0, W),
+    (-1, -1, NW),
+];
+
+// Corners only matter when both edges next to
+// them are set, otherwise the tile looks the
+// same either way. Clearing them reduces the
+// 256 possible masks to the 47 blob tiles.
+pub fn reduce(mask: u8) -> u8 {
+    let mut reduced = mask & (N | E | S | W);
+    for (corner, a, b) in [(NE, N, E), (SE, S, E), (SW, S, W), (NW, N, W)] {
+        if mask & corner != 0 && mask & a != 0 && mask & b != 0 {
+            reduced |= corner;
+        }
+    }
+    reduced
+}
+
+#[derive(Debug)]
+pub struct Autotile {
+    // Tilesheet indices per reduced mask, where
+    // the first one is the most common variant.
+    variants: Vec<Vec<u8>>,
+}
+
+impl Autotile {
+    // Walls with ground below show their side,
+    // everything else shows the top. These are
+    // the first rows below the ground sprites
+    // of a sheet with the given size.
+    pub fn new(columns: u8, rows: u8) -> io::Result<Self> {
+        let columns = columns as usize;
+        let row = |y: usize| {
+            (y * columns..y * columns + 5)
+                .map(|i| sprite(i, columns, rows))
+                .collect::<io::Result<Vec<_>>>()
+        };
+        let (top, side) = (row(1)?, row(2)?);
+
+        let variants = (0..=u8::MAX)
+            .map(|mask| {
+                if mask & S == 0 {
+                    side.clone()
+                } else {
+                    top.clone()
+                }
+            })
+            .collect();
+
+        Ok(Self { variants })
+    }
+
+    // Overrides the defaults with a file of lines
+    // on the form `<mask>: <index> <index> ...`.
+    // Empty lines and lines starting with # are
+    // ignored.
+    pub fn load(path: impl AsRef<Path>, columns: u8, rows: u8) -> io::Result<Self> {
+        let mut autotile = Self::new(columns, rows)?;
+
+        for (n, line) in read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid autotile mapping on line {}: {}", n + 1, line),
+                )
+            };
+
+            let (mask, indices) = line.split_once(':').ok_or_else(invalid)?;
+            let mask = mask.trim().parse::<u8>().map_err(|_| invalid())?;
+            let indices = indices
+                .split_whitespace()
+                .map(|i| {
+                    let i = i.parse::<usize>().map_err(|_| invalid())?;
+                    sprite(i, columns as usize, rows).map_err(|err| {
+                        io::Error::new(err.kind(), format!("line {}: {}", n + 1, err))
+                    })
+                })
+                .collect::<io::Result<Vec<u8>>>()?;
+            if indices.is_empty() {
+                return Err(invalid());
+            }
+
+            autotile.set(mask, indices);
+        }
+
+        Ok(autotile)
+    }
+
+    pub fn set(&mut self, mask: u8, indices: Vec<u8>) {
+        self.variants[reduce(mask) as usize] = indices;
+    }
+
+    pub fn pick(&self, rng: &mut StdRng, mask: u8) -> u8 {
+        let variants = &self.variants[reduce(mask) as usize];
+        if variants.len() == 1 || rng.gen_ratio(85, 100) {
+            return variants[0];
+        }
+        variants[rng.gen_range(0..variants.len())]
+    }
+}
+
+// Sprite indices are a u8, and have to be
+// inside the sheet.
+fn sprite(index: usize, columns: usize, rows: u8) -> io::Result<u8> {
+    if index < columns * rows as usize {
+        if let Ok(index) = u8::try_from(index) {
+            return Ok(index);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "autotile sprite {} is outside of the {}x{} sheet",
+            index, columns, rows
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{layer::LayerKind, test_util, tile::Tile, tiles::Tiles};
+
+    // Gives every reduced mask its own single
+    // variant, so sprites can be compared to
+    // masks directly.
+    fn identity() -> Autotile {
+        let mut autotile = Autotile::new(16, 16).unwrap();
+        for mask in 0..=u8::MAX {
+            autotile.variants[mask as usize] = vec![reduce(mask)];
+        }
+        autotile
+    }
+
+    // One row per line, # for walls and
+    // anything else for ground.
+    fn grid(s: &str) -> Tiles {
+        let rows = s
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .collect::<Vec<_>>();
+        let width = rows[0].len();
+        let tiles = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| if c == '#' { 1 } else { 0 }))
+            .collect();
+        test_util::tiles_with(tiles, width, rows.len(), identity())
+    }
+
+    fn sprite(tiles: &Tiles, index: usize) -> u8 {
+        tiles.layer(LayerKind::Structure).sprites[index]
+    }
+
+    #[test]
+    fn reduce_drops_lone_corners() {
+        assert_eq!(reduce(NE), 0);
+        assert_eq!(reduce(N | NE), N);
+        assert_eq!(reduce(N | NE | E), N | NE | E);
+        assert_eq!(reduce(u8::MAX), u8::MAX);
+    }
+
+    #[test]
+    fn surrounded_wall_is_full_mask() {
+        let tiles = grid("###\n###\n###");
+        assert_eq!(sprite(&tiles, 4), u8::MAX);
+    }
+
+    #[test]
+    fn edges_and_corners() {
+        let tiles = grid(
+            "
+            #####
+            #...#
+            #.#.#
+            #...#
+            #####
+            ",
+        );
+        // Lone pillar in the middle.
+        assert_eq!(sprite(&tiles, 12), 0);
+        // Top edge, bordered by the map on
+        // its top side.
+        assert_eq!(sprite(&tiles, 2), N | NE | E | W | NW);
+        // Top left corner.
+        assert_eq!(sprite(&tiles, 0), N | NE | E | S | SW | W | NW);
+        // Left edge.
+        assert_eq!(sprite(&tiles, 10), N | S | SW | W | NW);
+    }
+
+    #[test]
+    fn inner_corner() {
+        let tiles = grid(
+            "
+            ###
+            ###
+            ##.
+            ",
+        );
+        assert_eq!(sprite(&tiles, 4), N | NE | E | S | SW | W | NW);
+    }
+
+    #[test]
+    fn changes_update_neighbours() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tiles = grid("###\n###\n###");
+
+        tiles.set_tile(&mut rng, 4, Tile(0));
+        assert_eq!(sprite(&tiles, 1), N | NE | E | W | NW);
+        assert_eq!(sprite(&tiles, 3), N | NW | W | SW | S);
+
+        tiles.set_tile(&mut rng, 4, Tile(1));
+        assert_eq!(sprite(&tiles, 1), u8::MAX);
+        assert_eq!(sprite(&tiles, 4), u8::MAX);
+    }
+
+    #[test]
+    fn default_shows_side_above_ground() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let autotile = Autotile::new(16, 16).unwrap();
+        assert!((32..=36).contains(&autotile.pick(&mut rng, N | E | W)));
+        assert!((16..=20).contains(&autotile.pick(&mut rng, u8::MAX)));
+    }
+
+    #[test]
+    fn sprites_have_to_fit_the_sheet() {
+        assert!(Autotile::new(16, 3).is_ok());
+        for (columns, rows) in [(16, 2), (200, 16), (255, 255)] {
+            let err = Autotile::new(columns, rows).expect_err("should fail");
+            assert!(err.to_string().contains("outside of the"), "{}", err);
+        }
+
+        let path = std::env::temp_dir().join(format!("werfs_autotile_{}", std::process::id()));
+        std::fs::write(&path, "# comment\n0: 16 17\n255: 256\n").unwrap();
+        let err = Autotile::load(&path, 16, 16).expect_err("should fail");
+        assert!(
+            err.to_string().contains("line 3: autotile sprite 256"),
+            "{}",
+            err
+        );
+        std::fs::write(&path, "0: 48\n").unwrap();
+        assert!(Autotile::load(&path, 16, 3).is_err());
+        assert!(Autotile::load(&path, 16, 4).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
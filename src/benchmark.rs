@@ -0,0 +1,244 @@
+use std::time::{Duration, Instant};
+
+use ::rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    cli::Options,
+    constants::CLUSTER_SIZE,
+    entities::WorldIndex,
+    paths::{self, Hpa},
+    sim::{Simulation, SYSTEMS},
+    tiles::Tiles,
+};
+
+// What both the benchmark binary and --mode
+// benchmark print. Path searches with --paths,
+// ticks otherwise.
+pub fn run(sim: &mut Simulation, options: &Options, seed: u64) -> String {
+    if let Some(searches) = options.paths {
+        let report = PathReport::run(&sim.level.tiles, searches, seed);
+        return if options.json {
+            report.json() + "\n"
+        } else {
+            report.text()
+        };
+    }
+
+    let report = Report::run(sim, options.ticks, seed);
+    if options.json {
+        report.json() + "\n"
+    } else {
+        report.text()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Stats {
+    // All in microseconds.
+    pub mean: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    pub fn new(samples: &[Duration]) -> Self {
+        let mut micros = samples
+            .iter()
+            .map(|d| d.as_nanos() as f64 / 1000.0)
+            .collect::<Vec<_>>();
+        if micros.is_empty() {
+            return Self {
+                mean: 0.0,
+                p95: 0.0,
+                max: 0.0,
+            };
+        }
+        micros.sort_by(f64::total_cmp);
+
+        // Nearest rank.
+        let rank = (micros.len() as f64 * 0.95).ceil() as usize;
+        Self {
+            mean: micros.iter().sum::<f64>() / micros.len() as f64,
+            p95: micros[rank.clamp(1, micros.len()) - 1],
+            max: micros[micros.len() - 1],
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SystemStats {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub werfs: usize,
+    pub ticks: usize,
+    pub seed: u64,
+    pub systems: Vec<SystemStats>,
+    pub tick: Stats,
+}
+
+impl Report {
+    pub fn run(sim: &mut Simulation, ticks: usize, seed: u64) -> Self {
+        let mut systems = vec![Vec::with_capacity(ticks); SYSTEMS.len()];
+        let mut totals = Vec::with_capacity(ticks);
+
+        for _ in 0..ticks {
+            let timings = sim.tick();
+            for (samples, timing) in systems.iter_mut().zip(timings) {
+                samples.push(timing);
+            }
+            totals.push(timings.iter().sum());
+        }
+
+        Self {
+            werfs: sim.population(),
+            ticks,
+            seed,
+            systems: SYSTEMS
+                .iter()
+                .zip(&systems)
+                .map(|(&name, samples)| SystemStats {
+                    name,
+                    stats: Stats::new(samples),
+                })
+                .collect(),
+            tick: Stats::new(&totals),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut out = format!(
+            "{} werfs, {} ticks, seed {}\n{:<12}{:>12}{:>12}{:>12}\n",
+            self.werfs, self.ticks, self.seed, "µs", "mean", "p95", "max"
+        );
+        let rows = self
+            .systems
+            .iter()
+            .map(|s| (s.name, s.stats))
+            .chain([("tick", self.tick)]);
+        for (name, stats) in rows {
+            out.push_str(&format!(
+                "{:<12}{:>12.1}{:>12.1}{:>12.1}\n",
+                name, stats.mean, stats.p95, stats.max
+            ));
+        }
+        out
+    }
+
+    pub fn json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report is always serializable")
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PathReport {
+    pub searches: usize,
+    pub found: usize,
+    pub level: (usize, usize, usize),
+    pub seed: u64,
+    // Building the graph, in microseconds.
+    pub build: f64,
+    pub flat: Stats,
+    pub hierarchical: Stats,
+    // How much more hierarchical paths cost
+    // than the cheapest, on average.
+    pub extra_cost: f64,
+}
+
+impl PathReport {
+    // Both search between the same random open
+    // cells.
+    pub fn run(tiles: &Tiles, searches: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let open = (0..tiles.len())
+            .filter(|&i| !tiles.is_blocked(i))
+            .map(|i| WorldIndex(i as i32))
+            .collect::<Vec<_>>();
+
+        let start = Instant::now();
+        let hpa = Hpa::new(tiles, CLUSTER_SIZE);
+        let build = start.elapsed();
+
+        let mut flat = Vec::with_capacity(searches);
+        let mut hierarchical = Vec::with_capacity(searches);
+        let mut found = 0;
+        let mut extra_cost = 0.0;
+        for _ in 0..searches {
+            if open.is_empty() {
+                break;
+            }
+            let from = open[rng.gen_range(0..open.len())];
+            let goal = open[rng.gen_range(0..open.len())];
+
+            let start = Instant::now();
+            let cheapest = paths::search(tiles, from, goal);
+            flat.push(start.elapsed());
+
+            let start = Instant::now();
+            let path = hpa.search(tiles, from, goal);
+            hierarchical.push(start.elapsed());
+
+            if let (Some((_, cheapest)), Some((_, cost))) = (cheapest, path) {
+                found += 1;
+                extra_cost += (cost as f64 / cheapest.max(1) as f64) - 1.0;
+            }
+        }
+
+        Self {
+            searches,
+            found,
+            level: (tiles.width, tiles.height, tiles.depth),
+            seed,
+            build: build.as_nanos() as f64 / 1000.0,
+            flat: Stats::new(&flat),
+            hierarchical: Stats::new(&hierarchical),
+            extra_cost: extra_cost / found.max(1) as f64,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let (width, height, depth) = self.level;
+        let mut out = format!(
+            "{} searches on {}x{}x{}, seed {}, {} found\n{:<12}{:>12}{:>12}{:>12}\n",
+            self.searches, width, height, depth, self.seed, self.found, "µs", "mean", "p95", "max"
+        );
+        for (name, stats) in [("flat", self.flat), ("hierarchical", self.hierarchical)] {
+            out.push_str(&format!(
+                "{:<12}{:>12.1}{:>12.1}{:>12.1}\n",
+                name, stats.mean, stats.p95, stats.max
+            ));
+        }
+        out.push_str(&format!(
+            "graph built in {:.1} µs, paths cost {:.1}% more\n",
+            self.build,
+            self.extra_cost * 100.0
+        ));
+        out
+    }
+
+    pub fn json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_use_nearest_rank() {
+        let samples = (1..=100).map(Duration::from_micros).collect::<Vec<_>>();
+        let stats = Stats::new(&samples);
+        assert_eq!(stats.mean, 50.5);
+        assert_eq!(stats.p95, 95.0);
+        assert_eq!(stats.max, 100.0);
+
+        let stats = Stats::new(&[Duration::from_micros(3)]);
+        assert_eq!((stats.p95, stats.max), (3.0, 3.0));
+    }
+}
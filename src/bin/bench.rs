@@ -0,0 +1,40 @@
+use std::{env, process};
+
+use mq_evaluation::{
+    assets::Manifest,
+    benchmark,
+    cli::{self, Command},
+    sim::Simulation,
+};
+
+// Same as the game in benchmark mode, minus
+// the options that only apply to the window.
+//
+//   cargo run --release --bin bench -- --cave --werfs 40000 --ticks 600 --json
+//   cargo run --release --bin bench -- --cave --cave-size 512x512 --paths 200
+fn main() {
+    let options = match cli::parse_benchmark(env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::BENCH_USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::BENCH_USAGE);
+            process::exit(2);
+        }
+    };
+
+    let seed = options.seed.unwrap_or_default();
+    let result = Manifest::load(options.assets.clone())
+        .and_then(|manifest| Simulation::new(&options, &manifest, seed));
+    let mut sim = match result {
+        Ok(sim) => sim,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    };
+
+    print!("{}", benchmark::run(&mut sim, &options, seed));
+}
@@ -1,12 +1,14 @@
-use crate::{constants, entities::State};
+use crate::entities::State;
 use std::process::exit;
 
 use hecs::EntityBuilder;
 use macroquad::prelude::*;
-use pathfinding::prelude::bfs;
 
 use crate::{
-    constants::TILE_SIZE, entities::Position, tile::Tile, tiles::Tiles, utils::v2_to_index,
+    constants::TILE_SIZE,
+    entities::{Position, WorldIndex},
+    paths::PathQueue,
+    tiles::Tiles,
 };
 
 pub struct Camera {
@@ -14,6 +16,8 @@ pub struct Camera {
     vel: Vec2,
     zoom_vel: Vec2,
     pub mpos: Vec2,
+    // Visible z-level.
+    pub z: usize,
 }
 
 impl Camera {
@@ -29,16 +33,20 @@ impl Camera {
             vel: vec2(0., 0.),
             zoom_vel: vec2(0., 0.),
             mpos: vec2(0., 0.),
+            z: 0,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn update(
         &mut self,
         dt: f32,
         world: &mut hecs::World,
-        entity: hecs::Entity,
+        entity: Option<hecs::Entity>,
         werf_pos: Option<Position>,
-        tiles: &mut Tiles,
+        tiles: &Tiles,
+        paths: &mut PathQueue,
+        editing: bool,
     ) {
         if is_key_down(KeyCode::Escape) {
             exit(0);
@@ -81,50 +89,55 @@ impl Camera {
             .zoom
             .clamp(vec2(min_zoom, min_zoom), vec2(max_zoom, max_zoom));
 
+        if is_key_pressed(KeyCode::PageUp) || is_key_pressed(KeyCode::Period) {
+            self.z = (self.z + 1).min(tiles.depth - 1);
+        } else if is_key_pressed(KeyCode::PageDown) || is_key_pressed(KeyCode::Comma) {
+            self.z = self.z.saturating_sub(1);
+        }
+
         let mut pos = self.cam.screen_to_world(mouse_position().into());
-        pos = pos / TILE_SIZE;
+        pos /= TILE_SIZE;
         pos = pos.max(vec2(0., 0.));
-        pos = pos.min(vec2((tiles.width as f32) - 1., (tiles.width as f32) - 1.));
+        pos = pos.min(vec2((tiles.width as f32) - 1., (tiles.height as f32) - 1.));
         pos = pos.abs();
         self.mpos = pos;
 
-        if is_mouse_button_pressed(MouseButton::Left) {
-            let Some(werf_pos) = werf_pos else {
-                return;
-            };
-
-            let mpos_idx = v2_to_index(self.mpos, tiles.width);
-
-            let Some(path) = bfs(
-                &werf_pos.to_world_index(tiles.width, true),
-                |p| p.successors(tiles),
-                |p| p.0 == mpos_idx as i32,
-            ) else {
+        // Clicks belong to the editor while it's open.
+        if !editing && is_mouse_button_pressed(MouseButton::Left) {
+            // Nobody to send when everyone died.
+            let (Some(entity), Some(werf_pos)) = (entity, werf_pos) else {
                 return;
             };
 
-            if constants::DEBUG_MOUSE_CLICK {
-                for i in 0..tiles.tiles.len() {
-                    if tiles.tiles[i] == Tile::Red {
-                        tiles.tiles[i] = Tile::Ground01;
-                    }
-                }
-
-                for idx in path.clone() {
-                    tiles.tiles[idx.0 as usize] = Tile::Red;
-                }
-            }
+            let goal =
+                WorldIndex::new(self.mpos.x as i32, self.mpos.y as i32, self.z as i32, tiles);
 
+            // The search runs with the next tick.
+            let handle = paths.submit(entity, werf_pos.to_world_index(tiles), goal);
             let mut builder = EntityBuilder::new();
-            builder.add(State::new_moving(path));
+            builder.add(State::new_waiting(handle, goal));
 
             // This should always succeed, hence panic.
             world
                 .insert(entity, builder.build())
-                .expect("failed to insert moving state");
+                .expect("failed to insert waiting state");
         }
     }
 
+    // The part of the world on screen.
+    pub fn view(&self) -> Rect {
+        let a = self.cam.screen_to_world(vec2(0.0, 0.0));
+        let b = self
+            .cam
+            .screen_to_world(vec2(screen_width(), screen_height()));
+        Rect::new(
+            a.x.min(b.x),
+            a.y.min(b.y),
+            (a.x - b.x).abs(),
+            (a.y - b.y).abs(),
+        )
+    }
+
     pub fn set_cam(&mut self, r: Option<RenderTarget>) {
         self.cam.render_target = r;
         set_camera(&self.cam);
@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+
+use ::rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::utils::xy_to_index;
+
+// Tile ids, as declared in data/tiles.toml.
+const GROUND: u8 = 0;
+const WALL: u8 = 1;
+
+// Chance for any cell to start out as a wall
+// before the automata smooths it into caves.
+const FILL_PERCENT: u32 = 45;
+const SMOOTH_STEPS: usize = 5;
+
+// Pockets smaller than this are filled in,
+// anything larger gets tunneled to the
+// main cave instead.
+const MIN_POCKET_SIZE: usize = 12;
+
+// spawn::many_werfs scatters werfs over the
+// free cells at least 8 tiles around the
+// spawn point. A band across all of that
+// keeps them from crowding a few cells.
+const SPAWN_HALF_WIDTH: usize = 9;
+const SPAWN_HALF_HEIGHT: usize = 2;
+
+pub struct Cave {
+    pub tiles: Vec<u8>,
+    pub width: usize,
+    // Tile coordinates of the spawn area center.
+    pub spawn: (usize, usize),
+}
+
+pub fn generate(seed: u64, width: usize, height: usize) -> Cave {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut tiles = (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            if is_border(x, y, width, height) || rng.gen_ratio(FILL_PERCENT, 100) {
+                WALL
+            } else {
+                GROUND
+            }
+        })
+        .collect::<Vec<u8>>();
+
+    for _ in 0..SMOOTH_STEPS {
+        tiles = smooth(&tiles, width, height);
+    }
+
+    let spawn = (width / 2, height / 2);
+    carve_spawn(&mut tiles, width, height, spawn);
+    connect_regions(&mut tiles, width, height, spawn);
+
+    Cave {
+        tiles,
+        width,
+        spawn,
+    }
+}
+
+fn is_border(x: usize, y: usize, width: usize, height: usize) -> bool {
+    x == 0 || y == 0 || x + 1 == width || y + 1 == height
+}
+
+// Standard 4-5 rule: a cell becomes a wall
+// if it has more than four wall neighbours,
+// and ground if it has less than four.
+fn smooth(tiles: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut next = tiles.to_vec();
+    for y in 0..height {
+        for x in 0..width {
+            let i = xy_to_index(x, y, width);
+            if is_border(x, y, width, height) {
+                next[i] = WALL;
+                continue;
+            }
+            let walls = wall_neighbours(tiles, width, x, y);
+            if walls > 4 {
+                next[i] = WALL;
+            } else if walls < 4 {
+                next[i] = GROUND;
+            }
+        }
+    }
+    next
+}
+
+fn wall_neighbours(tiles: &[u8], width: usize, x: usize, y: usize) -> usize {
+    let mut walls = 0;
+    for ny in y - 1..=y + 1 {
+        for nx in x - 1..=x + 1 {
+            if (nx, ny) != (x, y) && tiles[xy_to_index(nx, ny, width)] == WALL {
+                walls += 1;
+            }
+        }
+    }
+    walls
+}
+
+// Clipped to inside the border, caves too
+// small to have an inside get nothing.
+fn carve_spawn(tiles: &mut [u8], width: usize, height: usize, spawn: (usize, usize)) {
+    let (sx, sy) = spawn;
+    let x0 = sx.saturating_sub(SPAWN_HALF_WIDTH).max(1);
+    let x1 = (sx + SPAWN_HALF_WIDTH).min(width.saturating_sub(2));
+    let y0 = sy.saturating_sub(SPAWN_HALF_HEIGHT).max(1);
+    let y1 = (sy + SPAWN_HALF_HEIGHT).min(height.saturating_sub(2));
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            tiles[xy_to_index(x, y, width)] = GROUND;
+        }
+    }
+}
+
+// Finds every ground region, fills in the tiny
+// ones and tunnels the rest to the region
+// containing the spawn area, so that every
+// remaining ground tile is reachable from it.
+fn connect_regions(tiles: &mut [u8], width: usize, height: usize, spawn: (usize, usize)) {
+    let spawn_idx = xy_to_index(spawn.0, spawn.1, width);
+
+    let mut regions = regions(tiles, width, height);
+    let Some(main) = regions.iter().position(|r| r.contains(&spawn_idx)) else {
+        return;
+    };
+    let mut connected = vec![false; tiles.len()];
+    for &i in &regions.swap_remove(main) {
+        connected[i] = true;
+    }
+
+    for region in regions {
+        if region.len() < MIN_POCKET_SIZE {
+            for i in region {
+                tiles[i] = WALL;
+            }
+            continue;
+        }
+
+        tunnel(tiles, width, height, &region, &connected);
+        for i in region {
+            connected[i] = true;
+        }
+    }
+}
+
+fn regions(tiles: &[u8], width: usize, height: usize) -> Vec<Vec<usize>> {
+    let mut seen = vec![false; tiles.len()];
+    let mut regions = vec![];
+
+    for start in 0..tiles.len() {
+        if seen[start] || tiles[start] != GROUND {
+            continue;
+        }
+
+        let mut region = vec![];
+        let mut stack = vec![start];
+        seen[start] = true;
+
+        while let Some(i) = stack.pop() {
+            region.push(i);
+            let (x, y) = (i % width, i / width);
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if !seen[n] && tiles[n] == GROUND {
+                    seen[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+
+        regions.push(region);
+    }
+
+    regions
+}
+
+// Breadth first search outwards from the region,
+// straight through walls, until it hits the
+// connected area. The path back is carved out,
+// which makes for the shortest possible tunnel.
+fn tunnel(tiles: &mut [u8], width: usize, height: usize, region: &[usize], connected: &[bool]) {
+    let mut parent = vec![usize::MAX; tiles.len()];
+    let mut queue = VecDeque::new();
+    for &i in region {
+        parent[i] = i;
+        queue.push_back(i);
+    }
+
+    while let Some(i) = queue.pop_front() {
+        if connected[i] {
+            let mut curr = i;
+            while parent[curr] != curr {
+                tiles[curr] = GROUND;
+                curr = parent[curr];
+            }
+            return;
+        }
+
+        let (x, y) = (i % width, i / width);
+        let neighbours = [
+            (x > 1).then(|| i - 1),
+            (x + 2 < width).then(|| i + 1),
+            (y > 1).then(|| i - width),
+            (y + 2 < height).then(|| i + width),
+        ];
+        for n in neighbours.into_iter().flatten() {
+            if parent[n] == usize::MAX {
+                parent[n] = i;
+                queue.push_back(n);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{CAVE_HEIGHT, CAVE_WIDTH};
+
+    #[test]
+    fn the_same_seed_gives_the_same_cave() {
+        let a = generate(7, CAVE_WIDTH, CAVE_HEIGHT);
+        let b = generate(7, CAVE_WIDTH, CAVE_HEIGHT);
+        assert_eq!(a.tiles, b.tiles);
+        assert_eq!(a.spawn, b.spawn);
+        assert_ne!(a.tiles, generate(8, CAVE_WIDTH, CAVE_HEIGHT).tiles);
+    }
+
+    #[test]
+    fn the_spawn_area_is_carved_and_connected() {
+        for seed in 0..20 {
+            let cave = generate(seed, CAVE_WIDTH, CAVE_HEIGHT);
+            let (sx, sy) = cave.spawn;
+
+            for y in sy - SPAWN_HALF_HEIGHT..=sy + SPAWN_HALF_HEIGHT {
+                for x in sx - SPAWN_HALF_WIDTH..=sx + SPAWN_HALF_WIDTH {
+                    assert_eq!(cave.tiles[xy_to_index(x, y, cave.width)], GROUND);
+                }
+            }
+
+            // One region, so all ground is reachable
+            // from the spawn.
+            let regions = regions(&cave.tiles, cave.width, CAVE_HEIGHT);
+            assert_eq!(regions.len(), 1, "seed {}", seed);
+            assert!(regions[0].contains(&xy_to_index(sx, sy, cave.width)));
+        }
+    }
+
+    #[test]
+    fn tiny_caves_still_generate() {
+        for (width, height) in [(0, 0), (1, 1), (2, 5), (3, 3), (5, 2)] {
+            let cave = generate(0, width, height);
+            assert_eq!(cave.tiles.len(), width * height);
+        }
+        let cave = generate(0, 3, 3);
+        assert_eq!(cave.tiles[xy_to_index(1, 1, 3)], GROUND);
+    }
+}
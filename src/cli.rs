@@ -0,0 +1,361 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::constants::{CAVE_HEIGHT, CAVE_WIDTH, MIN_CAVE_HEIGHT, MIN_CAVE_WIDTH};
+
+pub const USAGE: &str = "\
+Usage: werfs [OPTIONS] [LEVEL]
+
+Arguments:
+  [LEVEL]            level file, digits or a Tiled .tmx, .tmj or
+                     .json map, or a .png (see data/palette.toml).
+                     Defaults to the level in the asset manifest,
+                     or a generated cave if it's missing
+
+Options:
+  --cave             generate a cave instead of loading a level
+  --cave-size <WxH>  size of generated caves in tiles [default: 96x64]
+  --seed <N>         seed for everything random, caves included
+  --werfs <N>        werfs to spawn [default: 2]
+  --window <WxH>     window size in pixels [default: 800x600]
+  --mode <MODE>      interactive, headless or benchmark
+                     [default: interactive]
+  --ticks <N>        ticks to run without a window [default: 1000]
+  --json             print benchmark results as JSON
+  --paths <N>        in benchmark mode, time N path searches between
+                     random cells, flat and hierarchical, instead of
+                     running ticks
+  --view <N>         in headless mode, run in real time and show
+                     the level in the terminal every N ticks
+  --script <FILE>    in headless mode, run console commands from
+                     a file, one per line, before the ticks
+  --assets <DIR>     asset root, where data/assets.toml is
+  -h, --help         print this
+";
+
+pub const BENCH_USAGE: &str = "\
+Usage: bench [OPTIONS] [LEVEL]
+
+Runs the simulation without a window and prints how long each
+system took, or times path searches with --paths.
+
+Arguments:
+  [LEVEL]            level file, as for the game
+
+Options:
+  --cave             generate a cave instead of loading a level
+  --cave-size <WxH>  size of generated caves in tiles [default: 96x64]
+  --seed <N>         seed for everything random [default: 0]
+  --werfs <N>        werfs to spawn [default: 2]
+  --ticks <N>        ticks to run [default: 1000]
+  --json             print results as JSON
+  --paths <N>        time N path searches between random cells,
+                     flat and hierarchical, instead of running ticks
+  --assets <DIR>     asset root, where data/assets.toml is
+  -h, --help         print this
+";
+
+// Options of the game that mean nothing to
+// the benchmark binary.
+const GAME_ONLY: [&str; 4] = ["--window", "--mode", "--view", "--script"];
+
+// Options the other modes would ignore, with
+// the modes that use them. Given anyway, the
+// mode is most likely mistyped.
+const MODE_ONLY: [(&str, &[Mode], &str); 5] = [
+    (
+        "--ticks",
+        &[Mode::Headless, Mode::Benchmark],
+        "headless or benchmark",
+    ),
+    ("--json", &[Mode::Benchmark], "benchmark"),
+    ("--paths", &[Mode::Benchmark], "benchmark"),
+    ("--view", &[Mode::Headless], "headless"),
+    ("--script", &[Mode::Headless], "headless"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Interactive,
+    // Runs the simulation without a window.
+    Headless,
+    // Like headless, timing every tick.
+    Benchmark,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelSource {
+    // From the asset manifest, or a cave.
+    Default,
+    File(String),
+    Cave,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub level: LevelSource,
+    pub cave_size: (usize, usize),
+    pub seed: Option<u64>,
+    pub werfs: usize,
+    pub window: (i32, i32),
+    pub mode: Mode,
+    pub ticks: usize,
+    pub json: bool,
+    pub paths: Option<usize>,
+    pub view: Option<usize>,
+    pub script: Option<String>,
+    pub assets: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            level: LevelSource::Default,
+            cave_size: (CAVE_WIDTH, CAVE_HEIGHT),
+            seed: None,
+            werfs: 2,
+            window: (800, 600),
+            mode: Mode::Interactive,
+            ticks: 1000,
+            json: false,
+            paths: None,
+            view: None,
+            script: None,
+            assets: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Options),
+    Help,
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    parse_as(args, Options::default())
+}
+
+// Starting from the given defaults.
+fn parse_as(
+    args: impl IntoIterator<Item = String>,
+    mut options: Options,
+) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let mut given = vec![];
+
+    while let Some(arg) = args.next() {
+        given.push(arg.clone());
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--cave" => options.level = set_level(&options.level, LevelSource::Cave)?,
+            "--cave-size" => options.cave_size = size(&arg, "512x512", &value()?)?,
+            "--seed" => options.seed = Some(number(&arg, &value()?)?),
+            "--werfs" => options.werfs = number(&arg, &value()?)?,
+            "--window" => options.window = size(&arg, "800x600", &value()?)?,
+            "--mode" => options.mode = mode(&value()?)?,
+            "--ticks" => options.ticks = number(&arg, &value()?)?,
+            "--json" => options.json = true,
+            "--paths" => options.paths = Some(number(&arg, &value()?)?),
+            "--view" => options.view = Some(number(&arg, &value()?)?),
+            "--script" => options.script = Some(value()?),
+            "--assets" => options.assets = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => options.level = set_level(&options.level, LevelSource::File(arg.clone()))?,
+        }
+    }
+
+    if options.werfs == 0 {
+        return Err("--werfs must be at least 1".to_string());
+    }
+    let (width, height) = options.cave_size;
+    if width < MIN_CAVE_WIDTH || height < MIN_CAVE_HEIGHT {
+        return Err(format!(
+            "--cave-size must be at least {}x{}",
+            MIN_CAVE_WIDTH, MIN_CAVE_HEIGHT
+        ));
+    }
+    if options.paths == Some(0) {
+        return Err("--paths must be at least 1".to_string());
+    }
+    if options.view == Some(0) {
+        return Err("--view must be at least 1".to_string());
+    }
+    for (option, modes, names) in MODE_ONLY {
+        if given.iter().any(|arg| arg == option) && !modes.contains(&options.mode) {
+            return Err(format!("{} needs --mode {}", option, names));
+        }
+    }
+
+    Ok(Command::Run(options))
+}
+
+// For the benchmark binary, which is always
+// in benchmark mode. The seed defaults to 0
+// so runs are comparable.
+pub fn parse_benchmark(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let args = args.into_iter().collect::<Vec<_>>();
+    if let Some(arg) = args.iter().find(|arg| GAME_ONLY.contains(&arg.as_str())) {
+        return Err(format!("{} does not apply to the benchmark", arg));
+    }
+
+    let options = Options {
+        mode: Mode::Benchmark,
+        ..Options::default()
+    };
+    match parse_as(args, options)? {
+        Command::Run(options) => Ok(Command::Run(Options {
+            seed: options.seed.or(Some(0)),
+            ..options
+        })),
+        Command::Help => Ok(Command::Help),
+    }
+}
+
+fn set_level(current: &LevelSource, level: LevelSource) -> Result<LevelSource, String> {
+    match current {
+        LevelSource::Default => Ok(level),
+        _ => Err("only one level, or --cave, can be given".to_string()),
+    }
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a non-negative number, got {}", option, value))
+}
+
+fn size<T: FromStr + PartialOrd + Default>(
+    option: &str,
+    example: &str,
+    value: &str,
+) -> Result<(T, T), String> {
+    let invalid = || {
+        format!(
+            "{} expects WIDTHxHEIGHT, like {}, got {}",
+            option, example, value
+        )
+    };
+    let (w, h) = value.split_once('x').ok_or_else(invalid)?;
+    match (w.parse::<T>(), h.parse::<T>()) {
+        (Ok(w), Ok(h)) if w > T::default() && h > T::default() => Ok((w, h)),
+        _ => Err(invalid()),
+    }
+}
+
+fn mode(value: &str) -> Result<Mode, String> {
+    match value {
+        "interactive" => Ok(Mode::Interactive),
+        "headless" => Ok(Mode::Headless),
+        "benchmark" => Ok(Mode::Benchmark),
+        _ => Err(format!(
+            "--mode expects interactive, headless or benchmark, got {}",
+            value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &str) -> Result<Options, String> {
+        match parse(args.split_whitespace().map(str::to_string))? {
+            Command::Run(options) => Ok(options),
+            Command::Help => panic!("expected options"),
+        }
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = run(
+            "--cave --cave-size 512x512 --seed 7 --werfs 100 --window 1280x720 --mode headless",
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                level: LevelSource::Cave,
+                cave_size: (512, 512),
+                seed: Some(7),
+                werfs: 100,
+                window: (1280, 720),
+                mode: Mode::Headless,
+                ..Options::default()
+            }
+        );
+        assert_eq!(run("").unwrap(), Options::default());
+        assert_eq!(
+            run("level.png").unwrap().level,
+            LevelSource::File("level.png".to_string())
+        );
+        assert_eq!(
+            parse(["--werfs".to_string(), "-h".to_string()]),
+            Err("--werfs expects a non-negative number, got -h".to_string())
+        );
+        assert_eq!(parse(["--help".to_string()]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn invalid_options_are_explained() {
+        for (args, error) in [
+            ("--seed", "--seed needs a value"),
+            (
+                "--werfs lots",
+                "--werfs expects a non-negative number, got lots",
+            ),
+            ("--werfs 0", "--werfs must be at least 1"),
+            (
+                "--window 800",
+                "--window expects WIDTHxHEIGHT, like 800x600, got 800",
+            ),
+            (
+                "--mode fast",
+                "--mode expects interactive, headless or benchmark, got fast",
+            ),
+            ("--cave level", "only one level, or --cave, can be given"),
+            ("--fast", "unknown option --fast"),
+            ("--view 10", "--view needs --mode headless"),
+            ("--mode headless --view 0", "--view must be at least 1"),
+            ("--script setup.txt", "--script needs --mode headless"),
+            ("--cave-size 8x8", "--cave-size must be at least 24x8"),
+            (
+                "--cave-size 512",
+                "--cave-size expects WIDTHxHEIGHT, like 512x512, got 512",
+            ),
+            ("--mode benchmark --paths 0", "--paths must be at least 1"),
+            ("--ticks 10", "--ticks needs --mode headless or benchmark"),
+            ("--json --mode headless", "--json needs --mode benchmark"),
+            (
+                "--mode headless --paths 10",
+                "--paths needs --mode benchmark",
+            ),
+        ] {
+            assert_eq!(run(args), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn the_benchmark_takes_fewer_options() {
+        let bench = |args: &str| parse_benchmark(args.split_whitespace().map(str::to_string));
+
+        let Ok(Command::Run(options)) = bench("--cave --werfs 10 --json --paths 5 --ticks 9")
+        else {
+            panic!("expected options");
+        };
+        assert_eq!((options.mode, options.seed), (Mode::Benchmark, Some(0)));
+        assert_eq!(
+            (options.json, options.paths, options.ticks),
+            (true, Some(5), 9)
+        );
+        assert_eq!(
+            bench("--window 800x600"),
+            Err("--window does not apply to the benchmark".to_string())
+        );
+        assert_eq!(
+            bench("--mode headless"),
+            Err("--mode does not apply to the benchmark".to_string())
+        );
+    }
+}
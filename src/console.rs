@@ -0,0 +1,520 @@
+use std::{fs::read_to_string, io};
+
+use ::rand::{rngs::StdRng, SeedableRng};
+use hecs::Entity;
+use macroquad::prelude::*;
+
+use crate::{
+    constants::TILE_SIZE,
+    entities::{Position, State},
+    script::Message,
+    sim::Simulation,
+    spawn,
+};
+
+// Ten minutes at 60 ticks a second, anything
+// longer is better done with --ticks.
+const MAX_STEPS: usize = 36_000;
+
+pub const COMMANDS: [&str; 13] = [
+    "help", "spawn", "teleport", "set_tile", "dig", "behave", "lua", "kill", "seed", "pause",
+    "step", "stats", "clear",
+];
+
+const HELP: &str = "\
+spawn <n> [x y]          spawn n werfs around a tile, or the spawn point
+teleport <werf> <x> <y>  move a werf, by id, to a tile
+set_tile <x> <y> <type>  place a tile, by name or id
+dig <x> <y>              dig out a tile
+behave <werf> <name>     hand a werf to a Lua behaviour
+lua <code>               run Lua, as a script would
+kill [werf]              kill a werf, or all of them
+seed [n]                 show the seed, or reseed
+pause                    pause or resume
+step [n]                 run n ticks, 1 by default, at most 36000
+stats                    werfs, ticks, level size and totals
+clear                    clear the console";
+
+// Commands work on level z, which is the one
+// in view, or the spawn level in scripts.
+pub fn run(sim: &mut Simulation, line: &str, z: usize) -> Result<String, String> {
+    let args = line.split_whitespace().collect::<Vec<_>>();
+    let Some((&command, args)) = args.split_first() else {
+        return Ok(String::new());
+    };
+
+    match (command, args) {
+        ("help", []) => Ok(HELP.to_string()),
+        ("spawn", [n]) => spawn(sim, number(n)?, sim.spawn),
+        ("spawn", [n, x, y]) => {
+            let at = tile_position(sim, number(x)?, number(y)?, z)?;
+            spawn(sim, number(n)?, at)
+        }
+        ("teleport", [werf, x, y]) => {
+            let (x, y) = (number(x)?, number(y)?);
+            let at = tile_position(sim, x, y, z)?;
+            let werf = alive(sim, number(werf)?)?;
+            let tiles = &sim.level.tiles;
+            if tiles
+                .index_at(x, y, z as i32)
+                .is_some_and(|i| tiles.is_blocked(i))
+            {
+                return Err(format!("{},{} is blocked", x, y));
+            }
+            // Its path was from where it was.
+            sim.requests.cancel(werf);
+            let (pos, state) = sim
+                .world
+                .query_one_mut::<(&mut Position, &mut State)>(werf)
+                .map_err(|_| "that is not a werf".to_string())?;
+            // Centered, werfs are half a tile.
+            pos.p = at.p + TILE_SIZE / 4.0;
+            pos.z = at.z;
+            *state = State::Idle;
+            Ok(format!("teleported {}", werf.id()))
+        }
+        ("dig", [x, y]) => {
+            let tile = sim
+                .dig(number(x)?, number(y)?, z as i32)
+                .ok_or_else(|| "nothing to dig there".to_string())?;
+            Ok(format!(
+                "dug out {}",
+                sim.level.tiles.registry.get(tile).name
+            ))
+        }
+        ("behave", [werf, name]) => {
+            let werf = alive(sim, number(werf)?)?;
+            sim.requests.cancel(werf);
+            sim.world
+                .insert_one(werf, State::Scripted(name.to_string()))
+                .map_err(|_| "that is not a werf".to_string())?;
+            Ok(format!("{} is now {}", werf.id(), name))
+        }
+        ("lua", [_, ..]) => {
+            let code = line.trim_start()["lua".len()..].trim();
+            sim.scripts.run("console", code, &sim.level.tiles);
+            Ok(String::new())
+        }
+        ("set_tile", [x, y, name]) => {
+            let tiles = &sim.level.tiles;
+            let tile = match name.parse::<u8>() {
+                Ok(id) if tiles.registry.contains(id) => Some(crate::tile::Tile(id)),
+                _ => tiles.registry.by_name(name),
+            }
+            .ok_or_else(|| format!("unknown tile {}", name))?;
+            let index = tiles
+                .index_at(number(x)?, number(y)?, z as i32)
+                .ok_or_else(|| "outside of the level".to_string())?;
+            sim.level.tiles.set_tile(&mut sim.rng, index, tile);
+            Ok(format!("set {},{} to {}", x, y, name))
+        }
+        ("kill", []) => {
+            let killed = werfs(sim)
+                .into_iter()
+                .filter(|&werf| sim.kill(werf))
+                .count();
+            Ok(format!("killed {} werfs", killed))
+        }
+        ("kill", [werf]) => {
+            let werf = alive(sim, number(werf)?)?;
+            sim.kill(werf);
+            Ok(format!("killed {}", werf.id()))
+        }
+        ("seed", []) => Ok(format!("seed {}", sim.seed)),
+        ("seed", [seed]) => {
+            sim.seed = number(seed)?;
+            sim.rng = StdRng::seed_from_u64(sim.seed);
+            sim.scripts.reseed(sim.seed);
+            Ok(format!("seed {}", sim.seed))
+        }
+        ("pause", []) => {
+            sim.paused = !sim.paused;
+            Ok(if sim.paused { "paused" } else { "resumed" }.to_string())
+        }
+        ("step", []) => step(sim, 1),
+        ("step", [n]) => step(sim, number(n)?),
+        // The console window clears its own log,
+        // without one there is nothing to clear.
+        ("clear", []) => Ok(String::new()),
+        ("stats", []) => {
+            let tiles = &sim.level.tiles;
+            let moving = sim
+                .world
+                .query::<&State>()
+                .iter()
+                .filter(|(_, state)| matches!(state, State::Moving(_)))
+                .count();
+            let stats = &sim.stats;
+            Ok(format!(
+                "{} werfs, {} moving, {} paths queued, tick {}, level {}x{}x{}\n\
+                 {} arrivals, {} failed paths, {} collisions, {} tiles changed, {} dug, {} deaths",
+                sim.population(),
+                moving,
+                sim.requests.waiting(),
+                sim.ticks,
+                tiles.width,
+                tiles.height,
+                tiles.depth,
+                stats.arrivals,
+                stats.paths_failed,
+                stats.collisions,
+                stats.tiles_changed,
+                stats.dug,
+                stats.deaths
+            ))
+        }
+        _ if COMMANDS.contains(&command) => Err("wrong arguments, see help".to_string()),
+        _ => Err(format!("unknown command {}", command)),
+    }
+}
+
+// Lines starting with # are comments. Stops
+// at the first failing command.
+pub fn run_script(sim: &mut Simulation, path: &str, z: usize) -> io::Result<()> {
+    for (n, line) in read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let result = run(sim, line, z);
+        print_messages(sim);
+        match result {
+            Ok(out) if out.is_empty() => (),
+            Ok(out) => println!("{}", out),
+            Err(err) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}:{}: {}: {}", path, n + 1, line, err),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+// What scripts logged, for runs without a
+// window.
+pub fn print_messages(sim: &Simulation) {
+    for message in sim.scripts.take_messages() {
+        match message {
+            Message::Log(line) => println!("{}", line),
+            Message::Error(err) => eprintln!("warning: {}", err),
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse()
+        .map_err(|_| format!("{} is not a valid number", s))
+}
+
+fn tile_position(sim: &Simulation, x: i32, y: i32, z: usize) -> Result<Position, String> {
+    sim.tile_position(x, y, z as i32)
+        .ok_or_else(|| "outside of the level".to_string())
+}
+
+fn werfs(sim: &Simulation) -> Vec<Entity> {
+    sim.world
+        .query::<&Position>()
+        .iter()
+        .map(|(entity, _)| entity)
+        .collect()
+}
+
+fn alive(sim: &Simulation, id: u32) -> Result<Entity, String> {
+    let werf = sim.find(id).ok_or_else(|| format!("no werf {}", id))?;
+    match sim.world.get::<&State>(werf) {
+        Ok(state) if state.is_dead() => Err(format!("werf {} is dead", id)),
+        _ => Ok(werf),
+    }
+}
+
+fn spawn(sim: &mut Simulation, n: usize, at: Position) -> Result<String, String> {
+    let before = sim.population();
+    spawn::many_werfs(&mut sim.world, &mut sim.rng, &sim.level.tiles, at, n);
+    Ok(format!("spawned {} werfs", sim.population() - before))
+}
+
+fn step(sim: &mut Simulation, n: usize) -> Result<String, String> {
+    if n > MAX_STEPS {
+        return Err(format!("step runs at most {} ticks", MAX_STEPS));
+    }
+    for _ in 0..n {
+        sim.tick();
+    }
+    Ok(format!("tick {}", sim.ticks))
+}
+
+// Completes the command, or the tile type for
+// set_tile. With several matches, completes
+// as far as they agree and returns them all.
+pub fn complete(sim: &Simulation, line: &str) -> (String, Vec<String>) {
+    let words = line.split(' ').collect::<Vec<_>>();
+    let (last, done) = words.split_last().unwrap_or((&"", &[]));
+
+    let candidates = match done {
+        [] => COMMANDS.iter().map(|c| c.to_string()).collect(),
+        ["set_tile", _, _] => sim
+            .level
+            .tiles
+            .registry
+            .iter()
+            .map(|def| def.name.clone())
+            .collect(),
+        _ => vec![],
+    };
+    let matches = candidates
+        .into_iter()
+        .filter(|c| c.starts_with(last))
+        .collect::<Vec<_>>();
+
+    let Some(first) = matches.first() else {
+        return (line.to_string(), vec![]);
+    };
+    let common = matches.iter().fold(first.as_str(), |common, m| {
+        let len = common
+            .chars()
+            .zip(m.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        &common[..len]
+    });
+
+    let mut completed = done.iter().map(|w| format!("{} ", w)).collect::<String>();
+    completed.push_str(common);
+    if matches.len() == 1 {
+        completed.push(' ');
+        return (completed, vec![]);
+    }
+    (completed, matches)
+}
+
+const MAX_LOG: usize = 200;
+
+// Drops down with the ` key. Up and down go
+// through earlier commands, tab completes.
+#[derive(Debug, Default)]
+pub struct Console {
+    pub open: bool,
+    input: String,
+    history: Vec<String>,
+    // Position while browsing the history.
+    browsing: Option<usize>,
+    log: Vec<String>,
+}
+
+impl Console {
+    pub fn update(&mut self, sim: &mut Simulation, z: usize) {
+        let mut chars = vec![];
+        while let Some(c) = get_char_pressed() {
+            chars.push(c);
+        }
+
+        if is_key_pressed(KeyCode::GraveAccent) {
+            self.open = !self.open;
+            return;
+        }
+        if !self.open {
+            return;
+        }
+
+        // Queued newest first.
+        for c in chars.into_iter().rev() {
+            if !c.is_control() && c != '`' {
+                self.input.push(c);
+            }
+        }
+
+        if is_key_pressed(KeyCode::Backspace) {
+            self.input.pop();
+        }
+
+        if is_key_pressed(KeyCode::Tab) {
+            let (completed, matches) = complete(sim, &self.input);
+            self.input = completed;
+            if !matches.is_empty() {
+                self.push_log(matches.join("  "));
+            }
+        }
+
+        if is_key_pressed(KeyCode::Up) && !self.history.is_empty() {
+            let i = match self.browsing {
+                Some(i) => i.saturating_sub(1),
+                None => self.history.len() - 1,
+            };
+            self.browsing = Some(i);
+            self.input = self.history[i].clone();
+        } else if is_key_pressed(KeyCode::Down) {
+            if let Some(i) = self.browsing {
+                self.browsing = (i + 1 < self.history.len()).then_some(i + 1);
+                self.input = self
+                    .browsing
+                    .map_or(String::new(), |i| self.history[i].clone());
+            }
+        }
+
+        if is_key_pressed(KeyCode::Enter) {
+            let line = std::mem::take(&mut self.input);
+            self.browsing = None;
+            self.push_log(format!("> {}", line));
+            if line.trim().is_empty() {
+                return;
+            }
+            if self.history.last() != Some(&line) {
+                self.history.push(line.clone());
+            }
+
+            if line.trim() == "clear" {
+                self.log.clear();
+                return;
+            }
+            match run(sim, &line, z) {
+                Ok(out) => out.lines().for_each(|l| self.push_log(l.to_string())),
+                Err(err) => self.push_log(format!("error: {}", err)),
+            }
+        }
+    }
+
+    pub fn push_log(&mut self, line: String) {
+        self.log.push(line);
+        if self.log.len() > MAX_LOG {
+            self.log.remove(0);
+        }
+    }
+
+    pub fn draw(&self) {
+        if !self.open {
+            return;
+        }
+
+        let height = screen_height() / 3.0;
+        let line_height = 16.0;
+        draw_rectangle(
+            0.0,
+            0.0,
+            screen_width(),
+            height,
+            Color::new(0.0, 0.0, 0.0, 0.8),
+        );
+
+        let prompt_y = height - 8.0;
+        draw_text(
+            format!("> {}_", self.input).as_str(),
+            8.0,
+            prompt_y,
+            16.0,
+            WHITE,
+        );
+
+        let rows = ((height - line_height) / line_height) as usize;
+        for (i, line) in self.log.iter().rev().take(rows).enumerate() {
+            let y = prompt_y - line_height * (i + 1) as f32;
+            draw_text(line, 8.0, y, 16.0, LIGHTGRAY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entities::WorldIndex, test_util::sim};
+
+    #[test]
+    fn commands_change_the_simulation() {
+        let mut sim = sim();
+
+        run(&mut sim, "spawn 10", 0).unwrap();
+        assert_eq!(sim.population(), 12);
+        assert_eq!(sim.world.len(), 12);
+
+        // Teleporting forgets the path asked for.
+        let werf = werfs(&sim)[0];
+        let from = sim.level.tiles.index_at(1, 1, 0).unwrap();
+        let from = WorldIndex(from as i32);
+        sim.requests.submit(werf, from, from);
+        run(&mut sim, "set_tile 3 4 ground", 0).unwrap();
+        run(&mut sim, &format!("teleport {} 3 4", werf.id()), 0).unwrap();
+        let pos = *sim.world.get::<&Position>(werf).unwrap();
+        assert_eq!(pos.p, vec2(3.25, 4.25) * TILE_SIZE);
+        assert_eq!(sim.requests.waiting(), 0);
+
+        run(&mut sim, "set_tile 3 4 wall", 0).unwrap();
+        let index = sim.level.tiles.index_at(3, 4, 0).unwrap();
+        assert!(sim.level.tiles.is_blocked(index));
+        assert_eq!(
+            run(&mut sim, &format!("teleport {} 3 4", werf.id()), 0),
+            Err("3,4 is blocked".to_string())
+        );
+
+        sim.requests.submit(werf, from, from);
+        run(&mut sim, &format!("behave {} idle", werf.id()), 0).unwrap();
+        assert_eq!(sim.requests.waiting(), 0);
+
+        run(&mut sim, "step 5", 0).unwrap();
+        assert_eq!(sim.ticks, 5);
+
+        run(&mut sim, &format!("kill {}", werf.id()), 0).unwrap();
+        assert_eq!(sim.population(), 11);
+        assert_eq!(
+            run(&mut sim, &format!("teleport {} 3 4", werf.id()), 0),
+            Err(format!("werf {} is dead", werf.id()))
+        );
+        run(&mut sim, "kill", 0).unwrap();
+        assert_eq!(sim.population(), 0);
+        assert_eq!(sim.controlled, None);
+
+        // Dead werfs are removed after a while.
+        assert_eq!(sim.world.len(), 12);
+        run(&mut sim, "step 61", 0).unwrap();
+        assert_eq!(sim.world.len(), 0);
+        assert_eq!(sim.stats.deaths, 12);
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        let mut sim = sim();
+        assert_eq!(
+            run(&mut sim, "dance", 0),
+            Err("unknown command dance".to_string())
+        );
+        assert_eq!(
+            run(&mut sim, "spawn", 0),
+            Err("wrong arguments, see help".to_string())
+        );
+        assert_eq!(
+            run(&mut sim, "set_tile 1 1 lava", 0),
+            Err("unknown tile lava".to_string())
+        );
+        assert_eq!(
+            run(&mut sim, "kill 99999", 0),
+            Err("no werf 99999".to_string())
+        );
+        assert_eq!(
+            run(&mut sim, "step 1000000", 0),
+            Err(format!("step runs at most {} ticks", MAX_STEPS))
+        );
+        assert_eq!(sim.ticks, 0);
+        // Scripts can clear too, it does nothing.
+        assert_eq!(run(&mut sim, "clear", 0), Ok(String::new()));
+    }
+
+    #[test]
+    fn completion() {
+        let sim = sim();
+        assert_eq!(complete(&sim, "tel"), ("teleport ".to_string(), vec![]));
+        assert_eq!(
+            complete(&sim, "s"),
+            (
+                "s".to_string(),
+                vec!["spawn", "set_tile", "seed", "step", "stats"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            )
+        );
+        assert_eq!(
+            complete(&sim, "set_tile 1 2 stairs_d"),
+            ("set_tile 1 2 stairs_down ".to_string(), vec![])
+        );
+    }
+}
@@ -1,6 +1,54 @@
 // These are floats as it saves us
 // from having to do a lot of conversions.
 pub const TILE_SIZE: f32 = 16.0;
-pub const TILESHEET_WIDTH: f32 = 16.0;
 
-pub const DEBUG_MOUSE_CLICK: bool = false;
+// Werfs are half a tile, the radius reaches
+// from their corner to their centre.
+pub const COLLISION_RADIUS: f32 = TILE_SIZE / 4.0;
+
+// Moving werfs keep this far from others.
+pub const SEPARATION_RADIUS: f32 = COLLISION_RADIUS * 3.0;
+
+// Seconds between checks whether a moving
+// werf is still on its path.
+pub const REPATH_INTERVAL: f32 = 0.25;
+
+// Pushed further than this from its path, a
+// werf looks for a new one.
+pub const MAX_DRIFT: f32 = TILE_SIZE * 2.0;
+
+// Failed searches for a new path in a row
+// before a werf gives up.
+pub const REPATH_ATTEMPTS: u32 = 3;
+
+// Cells per side of the clusters of
+// hierarchical pathfinding.
+pub const CLUSTER_SIZE: usize = 16;
+
+// Time spent on path searches each tick, at
+// least one runs however long it takes.
+pub const PATH_BUDGET: std::time::Duration = std::time::Duration::from_millis(2);
+
+// Upper bound on tile changes kept for undo.
+pub const HISTORY_CHANGES: usize = 100_000;
+
+// Fixed time step without a window.
+pub const TICK: f32 = 1.0 / 60.0;
+
+// Seconds between animation frames.
+pub const ANIMATION_INTERVAL: f32 = 0.128;
+
+// Seconds dead werfs stay around before
+// they are removed.
+pub const DEATH_TIME: f32 = 1.0;
+
+// Seconds between checks for changed files.
+pub const RELOAD_INTERVAL: f64 = 0.5;
+
+// Size in tiles of generated caves.
+pub const CAVE_WIDTH: usize = 96;
+pub const CAVE_HEIGHT: usize = 64;
+
+// Smallest caves the spawn area fits in.
+pub const MIN_CAVE_WIDTH: usize = 24;
+pub const MIN_CAVE_HEIGHT: usize = 8;
@@ -0,0 +1,353 @@
+use ::rand::rngs::StdRng;
+use macroquad::prelude::*;
+
+use crate::{constants::TILE_SIZE, history::History, level::Level, tile::Tile, tiles::Tiles};
+
+// Generated and imported levels have no file
+// of their own to save to.
+const DEFAULT_SAVE_PATH: &str = "level_generated";
+
+const MAX_BRUSH_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    Brush,
+    Fill,
+    Rect,
+}
+
+pub struct Editor {
+    pub enabled: bool,
+    pub tool: Tool,
+    pub brush_size: usize,
+    pub tile: Tile,
+    // Corner where the rectangle tool was
+    // pressed, in tile coordinates.
+    rect_start: Option<(usize, usize)>,
+    status: String,
+}
+
+impl Editor {
+    pub fn new(tile: Tile) -> Self {
+        Self {
+            enabled: false,
+            tool: Tool::Brush,
+            brush_size: 1,
+            tile,
+            rect_start: None,
+            status: String::new(),
+        }
+    }
+
+    // Tab toggles the editor, Ctrl+Z and Ctrl+Y
+    // undo and redo at any time. While enabled:
+    //   B, F, R   brush, fill and rectangle tools
+    //   [ ]       brush size
+    //   Q E       previous and next tile type
+    //   Ctrl+S    save the level
+    //
+    // Edits are committed to the history right
+    // away, brush strokes are kept as one step
+    // until the mouse is released.
+    //
+    // Returns whether the level was saved, so
+    // the file changing isn't mistaken for an
+    // edit made elsewhere.
+    pub fn update(
+        &mut self,
+        rng: &mut StdRng,
+        level: &mut Level,
+        history: &mut History,
+        mpos: Vec2,
+        z: usize,
+    ) -> bool {
+        if is_key_pressed(KeyCode::Tab) {
+            self.enabled = !self.enabled;
+            self.rect_start = None;
+        }
+
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        if ctrl && is_key_pressed(KeyCode::Z) {
+            history.undo(&mut level.tiles);
+        } else if ctrl && is_key_pressed(KeyCode::Y) {
+            history.redo(&mut level.tiles);
+        }
+
+        if !self.enabled {
+            history.hold(false);
+            return false;
+        }
+
+        // Whatever changed since the last tick
+        // isn't part of the edit.
+        history.commit(&mut level.tiles);
+        let saved = self.edit(rng, level, ctrl, mpos, z);
+
+        let stroke = self.tool == Tool::Brush && is_mouse_button_down(MouseButton::Left);
+        history.hold(stroke);
+        history.commit_edit(&mut level.tiles);
+        saved
+    }
+
+    fn edit(
+        &mut self,
+        rng: &mut StdRng,
+        level: &mut Level,
+        ctrl: bool,
+        mpos: Vec2,
+        z: usize,
+    ) -> bool {
+        if is_key_pressed(KeyCode::B) {
+            self.tool = Tool::Brush;
+        } else if is_key_pressed(KeyCode::F) {
+            self.tool = Tool::Fill;
+        } else if is_key_pressed(KeyCode::R) {
+            self.tool = Tool::Rect;
+        }
+
+        if is_key_pressed(KeyCode::LeftBracket) {
+            self.brush_size = (self.brush_size - 1).max(1);
+        } else if is_key_pressed(KeyCode::RightBracket) {
+            self.brush_size = (self.brush_size + 1).min(MAX_BRUSH_SIZE);
+        }
+
+        if is_key_pressed(KeyCode::Q) {
+            self.tile = cycle(&level.tiles, self.tile, -1);
+        } else if is_key_pressed(KeyCode::E) {
+            self.tile = cycle(&level.tiles, self.tile, 1);
+        }
+
+        let mut saved = false;
+        if ctrl && is_key_pressed(KeyCode::S) {
+            let path = level.save_path().unwrap_or(DEFAULT_SAVE_PATH).to_string();
+            self.status = match level.save(&path) {
+                Ok(()) => {
+                    saved = true;
+                    format!("saved {}", path)
+                }
+                Err(err) => format!("failed to save {}: {}", path, err),
+            };
+        }
+
+        let (x, y) = (mpos.x as usize, mpos.y as usize);
+        let tiles = &mut level.tiles;
+
+        match self.tool {
+            Tool::Brush => {
+                if is_mouse_button_down(MouseButton::Left) {
+                    let (x0, y0, size) = self.brush_area(tiles, x, y);
+                    tiles.set_square(rng, x0, y0, z, size, self.tile);
+                }
+            }
+            Tool::Fill => {
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    flood_fill(rng, tiles, x, y, z, self.tile);
+                }
+            }
+            Tool::Rect => {
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    self.rect_start = Some((x, y));
+                }
+                if is_mouse_button_released(MouseButton::Left) {
+                    if let Some((sx, sy)) = self.rect_start.take() {
+                        let (x0, y0) = (sx.min(x), sy.min(y));
+                        let (w, h) = (sx.abs_diff(x) + 1, sy.abs_diff(y) + 1);
+                        tiles.set_rect(rng, x0, y0, z, w, h, self.tile);
+                    }
+                }
+            }
+        }
+
+        saved
+    }
+
+    // Drawn in world space, outlining the
+    // tiles that will be painted.
+    pub fn draw_cursor(&self, tiles: &Tiles, mpos: Vec2) {
+        if !self.enabled {
+            return;
+        }
+
+        let (x, y) = (mpos.x as usize, mpos.y as usize);
+        let (x0, y0, w, h) = match (self.tool, self.rect_start) {
+            (Tool::Brush, _) => {
+                let (x0, y0, size) = self.brush_area(tiles, x, y);
+                (x0, y0, size, size)
+            }
+            (Tool::Rect, Some((sx, sy))) => {
+                (sx.min(x), sy.min(y), sx.abs_diff(x) + 1, sy.abs_diff(y) + 1)
+            }
+            _ => (x, y, 1, 1),
+        };
+
+        draw_rectangle_lines(
+            x0 as f32 * TILE_SIZE,
+            y0 as f32 * TILE_SIZE,
+            w as f32 * TILE_SIZE,
+            h as f32 * TILE_SIZE,
+            1.0,
+            YELLOW,
+        );
+    }
+
+    pub fn draw_hud(&self, tiles: &Tiles) {
+        if !self.enabled {
+            return;
+        }
+
+        draw_text(
+            format!(
+                "EDITOR: {:?}, size {}, tile {}  {}",
+                self.tool,
+                self.brush_size,
+                tiles.registry.get(self.tile).name,
+                self.status,
+            )
+            .as_str(),
+            16.0,
+            48.0,
+            16.0,
+            YELLOW,
+        );
+    }
+
+    // Centered on the cursor and clamped
+    // to the level bounds.
+    fn brush_area(&self, tiles: &Tiles, x: usize, y: usize) -> (usize, usize, usize) {
+        let size = self.brush_size.min(tiles.width).min(tiles.height);
+        let x0 = x.saturating_sub(size / 2).min(tiles.width - size);
+        let y0 = y.saturating_sub(size / 2).min(tiles.height - size);
+        (x0, y0, size)
+    }
+}
+
+// Steps through the tile types, in id order.
+fn cycle(tiles: &Tiles, current: Tile, step: i32) -> Tile {
+    let paintable = tiles
+        .registry
+        .iter()
+        .map(|def| Tile(def.id))
+        .collect::<Vec<_>>();
+
+    let Some(i) = paintable.iter().position(|&t| t == current) else {
+        return paintable.first().copied().unwrap_or(current);
+    };
+
+    let len = paintable.len() as i32;
+    paintable[(i as i32 + step).rem_euclid(len) as usize]
+}
+
+// Replaces the 4-connected area that looks
+// like the tile under the cursor.
+fn flood_fill(rng: &mut StdRng, tiles: &mut Tiles, x: usize, y: usize, z: usize, t: Tile) {
+    let Some(start) = tiles.index_at(x as i32, y as i32, z as i32) else {
+        return;
+    };
+
+    let target = tiles.top_tile(start);
+    if target == Some(t) {
+        return;
+    }
+
+    let mut seen = vec![false; tiles.width * tiles.height];
+    let mut stack = vec![start];
+    let mut area = vec![];
+
+    while let Some(i) = stack.pop() {
+        let (x, y, z) = tiles.xyz(i);
+        let level_i = i % seen.len();
+        if seen[level_i] || tiles.top_tile(i) != target {
+            continue;
+        }
+        seen[level_i] = true;
+        area.push(i);
+
+        for (dx, dy) in [(0, -1), (1, 0), (0, 1), (-1, 0)] {
+            if let Some(n) = tiles.index_at(x + dx, y + dy, z) {
+                stack.push(n);
+            }
+        }
+    }
+
+    for i in area {
+        tiles.set_tile(rng, i, t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::SeedableRng;
+
+    use super::*;
+    use crate::test_util;
+
+    const GROUND: Tile = Tile(0);
+    const WALL: Tile = Tile(1);
+    const RAMP: Tile = Tile(4);
+
+    #[test]
+    fn fill_stops_at_other_tiles_and_levels() {
+        let mut rng = StdRng::seed_from_u64(0);
+        #[rustfmt::skip]
+        let mut tiles = test_util::tiles(
+            vec![
+                0, 0, 1, 0,
+                0, 1, 0, 0,
+                // z = 1
+                0, 0, 0, 0,
+                0, 0, 0, 0,
+            ],
+            4,
+            2,
+        );
+
+        flood_fill(&mut rng, &mut tiles, 0, 0, 0, RAMP);
+        let top = (0..tiles.len())
+            .map(|i| tiles.top_tile(i).unwrap())
+            .collect::<Vec<_>>();
+        #[rustfmt::skip]
+        assert_eq!(
+            top,
+            vec![
+                RAMP, RAMP, WALL, GROUND,
+                RAMP, WALL, GROUND, GROUND,
+                GROUND, GROUND, GROUND, GROUND,
+                GROUND, GROUND, GROUND, GROUND,
+            ]
+        );
+
+        // Outside of the level, or onto itself.
+        tiles.take_changes();
+        flood_fill(&mut rng, &mut tiles, 9, 0, 0, WALL);
+        flood_fill(&mut rng, &mut tiles, 0, 0, 0, RAMP);
+        assert!(tiles.take_changes().changes.is_empty());
+    }
+
+    #[test]
+    fn brushes_stay_inside_the_level() {
+        let tiles = test_util::tiles(vec![0; 6 * 4], 6, 4);
+        let mut editor = Editor::new(GROUND);
+
+        editor.brush_size = 3;
+        assert_eq!(editor.brush_area(&tiles, 2, 2), (1, 1, 3));
+        assert_eq!(editor.brush_area(&tiles, 0, 0), (0, 0, 3));
+        assert_eq!(editor.brush_area(&tiles, 5, 3), (3, 1, 3));
+
+        // Larger than the level, down to its
+        // smaller side.
+        editor.brush_size = MAX_BRUSH_SIZE;
+        assert_eq!(editor.brush_area(&tiles, 5, 3), (2, 0, 4));
+    }
+
+    #[test]
+    fn cycling_wraps_around() {
+        let tiles = test_util::tiles(vec![0], 1, 1);
+        let last = Tile(8);
+
+        assert_eq!(cycle(&tiles, GROUND, 1), WALL);
+        assert_eq!(cycle(&tiles, GROUND, -1), last);
+        assert_eq!(cycle(&tiles, last, 1), GROUND);
+        // Unknown to the editor, back to the start.
+        assert_eq!(cycle(&tiles, Tile(9), 1), GROUND);
+    }
+}
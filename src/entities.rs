@@ -1,69 +1,89 @@
 use kd_tree::KdPoint;
 use macroquad::prelude::*;
 
-use crate::{constants::TILE_SIZE, tiles::Tiles, utils::index_to_v2};
+use crate::{
+    constants::{MAX_DRIFT, REPATH_ATTEMPTS, REPATH_INTERVAL, SEPARATION_RADIUS, TILE_SIZE},
+    paths::{PathHandle, PathQueue, PathStatus},
+    steering,
+    tiles::Tiles,
+    tuning::Tuning,
+};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Position {
     pub p: Vec2,
+    // Z-level, only changes on stairs.
+    pub z: usize,
 }
 
 impl Position {
-    pub fn to_world_index(&self, world_width: usize, divide: bool) -> WorldIndex {
-        let mut x = self.p.x as i32;
-        let mut y = self.p.y as i32;
-        if divide {
-            x = x / TILE_SIZE as i32;
-            y = y / TILE_SIZE as i32;
-        }
-
-        let idx = x + y * world_width as i32;
+    pub fn to_world_index(self, tiles: &Tiles) -> WorldIndex {
+        let x = self.p.x as i32 / TILE_SIZE as i32;
+        let y = self.p.y as i32 / TILE_SIZE as i32;
 
-        WorldIndex(idx)
+        WorldIndex::new(x, y, self.z as i32, tiles)
     }
 }
 
+// Addresses a single cell across all z-levels.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct WorldIndex(pub i32);
 
 impl WorldIndex {
+    pub fn new(x: i32, y: i32, z: i32, tiles: &Tiles) -> Self {
+        let level_len = (tiles.width * tiles.height) as i32;
+        Self(x + y * tiles.width as i32 + z * level_len)
+    }
+
+    pub fn xyz(self, tiles: &Tiles) -> (i32, i32, i32) {
+        tiles.xyz(self.0 as usize)
+    }
+
+    pub fn z(self, tiles: &Tiles) -> usize {
+        self.xyz(tiles).2 as usize
+    }
+
     // Currently only returns cardinal directions.
     // Future implementation will include diagonal
     // directions, however these require an
     // accompanied cost as to compensate for the
     // extra distance they cover.
-    pub fn successors(&self, tiles: &Tiles) -> Vec<WorldIndex> {
-        let mut available = vec![];
-
-        if let Some(top) = tiles.tile_above(self.0 as usize) {
-            if !top.is_blocked() {
-                available.push(WorldIndex(self.0 - tiles.width as i32));
-            }
-        }
-
-        if let Some(right) = tiles.tile_at_right(self.0 as usize) {
-            if !right.is_blocked() {
-                available.push(WorldIndex(self.0 + 1 as i32));
-            }
-        }
+    //
+    // Stairs and ramps add the cell straight
+    // above or below them.
+    //
+    // Each successor comes with the cost of
+    // stepping onto it.
+    pub fn successors(&self, tiles: &Tiles) -> Vec<(WorldIndex, u32)> {
+        let (x, y, z) = self.xyz(tiles);
+        let climb = tiles.climb(self.0 as usize);
 
-        if let Some(bottom) = tiles.tile_below(self.0 as usize) {
-            if !bottom.is_blocked() {
-                available.push(WorldIndex(self.0 + tiles.width as i32));
-            }
-        }
-
-        if let Some(left) = tiles.tile_at_left(self.0 as usize) {
-            if !left.is_blocked() {
-                available.push(WorldIndex(self.0 - 1 as i32));
-            }
-        }
+        [
+            (0, -1, 0, true),
+            (1, 0, 0, true),
+            (0, 1, 0, true),
+            (-1, 0, 0, true),
+            (0, 0, 1, climb.up()),
+            (0, 0, -1, climb.down()),
+        ]
+        .into_iter()
+        .filter(|&(_, _, _, allowed)| allowed)
+        .filter_map(|(dx, dy, dz, _)| tiles.index_at(x + dx, y + dy, z + dz))
+        .filter(|&i| !tiles.is_blocked(i))
+        .map(|i| (WorldIndex(i as i32), tiles.cost(i)))
+        .collect()
+    }
 
-        available
+    pub fn distance(&self, other: &WorldIndex, tiles: &Tiles) -> u32 {
+        let (ax, ay, az) = self.xyz(tiles);
+        let (bx, by, bz) = other.xyz(tiles);
+        ax.abs_diff(bx) + ay.abs_diff(by) + az.abs_diff(bz)
     }
 
-    pub fn to_vec(&self, width: usize) -> Vec2 {
-        index_to_v2(self.0, width)
+    // Position within its z-level, in tiles.
+    pub fn to_vec(self, tiles: &Tiles) -> Vec2 {
+        let (x, y, _) = self.xyz(tiles);
+        vec2(x as f32, y as f32)
     }
 }
 
@@ -92,28 +112,167 @@ pub struct Animated {
 pub struct Moving {
     path: Vec<WorldIndex>,
     curr: usize,
+    // Until the werf checks it's still on its
+    // path.
+    check: f32,
+    // Failed searches since it last arrived.
+    failures: u32,
+}
+
+// What came of a werf's walking this tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    Walking,
+    Arrived,
+    // Off its path, it needs a new one to the
+    // goal.
+    Lost(WorldIndex),
+    // No path to the goal, the werf stopped.
+    Failed(WorldIndex),
 }
 
 impl Moving {
-    pub fn update(&mut self, world_width: usize, pos: &Position, vel: &mut Velocity, dt: f32) -> bool {
+    // Waypoints not yet reached.
+    pub fn remaining(&self) -> &[WorldIndex] {
+        &self.path[self.curr.min(self.path.len())..]
+    }
+
+    // Keeps its distance to the neighbours,
+    // which are the positions of werfs around
+    // it.
+    pub fn update(
+        &mut self,
+        tiles: &Tiles,
+        pos: &mut Position,
+        vel: &mut Velocity,
+        neighbours: &[Vec2],
+        dt: f32,
+        tuning: &Tuning,
+    ) -> Progress {
         if self.curr >= self.path.len() {
-            return true;
+            return Progress::Arrived;
         }
 
-        let target = self.path[self.curr].to_vec(world_width) * TILE_SIZE;
-        let dir = target - pos.p;
-
-        if dir.length_squared().abs() < 50.0 {
+        // The last waypoint is only reached once
+        // the werf has stopped on it, it would
+        // coast on otherwise.
+        let last = self.curr == self.path.len() - 1;
+        let target = steering::waypoint(self.path[self.curr], tiles);
+        let stopped = !last || vel.v.length_squared() < tuning.stop_threshold;
+        if pos.p.distance_squared(target) < tuning.waypoint_radius && stopped {
+            // Stairs are left by walking onto
+            // the next waypoint, which sits on
+            // the same spot one level over.
+            pos.z = self.path[self.curr].z(tiles);
             self.curr += 1;
             if self.curr >= self.path.len() {
-                return true;
+                return Progress::Arrived;
             }
         }
 
-        let speed = dir.normalize() * dt * 4.0;
-        vel.v += speed;
+        self.check -= dt;
+        if self.check <= 0.0 {
+            self.check = REPATH_INTERVAL;
+            if self.off_course(tiles, pos) {
+                return Progress::Lost(self.path[self.path.len() - 1]);
+            }
+        }
+
+        // Only the last waypoint is braked for,
+        // the ones before are walked through.
+        // Neighbours are ignored on the last
+        // stretch, or a werf standing on the
+        // target would keep others from ever
+        // reaching it.
+        let target = steering::waypoint(self.path[self.curr], tiles);
+        let desired = if self.curr == self.path.len() - 1 {
+            steering::arrive(pos.p, target, tuning.max_speed, tuning.arrive_radius)
+        } else {
+            steering::seek(pos.p, target, tuning.max_speed)
+                + steering::separation(pos.p, neighbours, SEPARATION_RADIUS)
+                    * tuning.max_speed
+                    * tuning.separation
+        };
+
+        vel.v += steering::steer(desired, vel.v, tuning.steering_speed * dt);
+
+        Progress::Walking
+    }
+
+    // Whether the next waypoint is blocked, out
+    // of sight, or the werf was pushed too far
+    // from the line leading to it.
+    fn off_course(&self, tiles: &Tiles, pos: &Position) -> bool {
+        let next = self.path[self.curr];
+        if tiles.is_blocked(next.0 as usize) {
+            return true;
+        }
+        // Seen from the level of the waypoint,
+        // werfs on stairs are between two.
+        let Some(here) = cell(pos, next.z(tiles), tiles) else {
+            return true;
+        };
+        if !steering::walkable(here, next, tiles) {
+            return true;
+        }
+
+        self.curr > 0 && {
+            let from = steering::waypoint(self.path[self.curr - 1], tiles);
+            let to = steering::waypoint(next, tiles);
+            steering::drift(pos.p, from, to) > MAX_DRIFT
+        }
+    }
+}
+
+// The cell the werf is in on level z, if it
+// is inside the level.
+fn cell(pos: &Position, z: usize, tiles: &Tiles) -> Option<WorldIndex> {
+    let p = (pos.p / TILE_SIZE).floor();
+    tiles
+        .index_at(p.x as i32, p.y as i32, z as i32)
+        .map(|i| WorldIndex(i as i32))
+}
+
+// Stands still until the path it asked for
+// comes back.
+#[derive(Debug)]
+pub struct Waiting {
+    handle: PathHandle,
+    goal: WorldIndex,
+    // Failed searches since it last arrived,
+    // kept when it gets lost on the way.
+    failures: u32,
+    // Until it asks again after a failure.
+    retry: f32,
+}
 
-        return false;
+impl Waiting {
+    // Returns the path once it's found.
+    fn update(
+        &mut self,
+        tiles: &Tiles,
+        paths: &mut PathQueue,
+        pos: &Position,
+        dt: f32,
+    ) -> Option<Vec<WorldIndex>> {
+        if self.retry > 0.0 {
+            self.retry -= dt;
+            if self.retry <= 0.0 {
+                let from = pos.to_world_index(tiles);
+                self.handle = paths.submit(self.handle.werf, from, self.goal);
+            }
+            return None;
+        }
+
+        match paths.take(self.handle) {
+            PathStatus::Waiting => None,
+            PathStatus::Found(path) => Some(path),
+            PathStatus::Failed => {
+                self.failures += 1;
+                self.retry = REPATH_INTERVAL;
+                None
+            }
+        }
     }
 }
 
@@ -121,22 +280,137 @@ impl Moving {
 pub enum State {
     Idle,
     Moving(Moving),
+    WaitingForPath(Waiting),
+    // Driven by the named Lua behaviour.
+    Scripted(String),
+    // Removed once the time left runs out.
+    Dead(f32),
 }
 
 impl State {
+    pub fn is_dead(&self) -> bool {
+        matches!(self, State::Dead(_))
+    }
+
     pub fn new_moving(path: Vec<WorldIndex>) -> State {
-        Self::Moving(Moving { path, curr: 0 })
+        Self::Moving(Moving {
+            path,
+            curr: 0,
+            check: REPATH_INTERVAL,
+            failures: 0,
+        })
+    }
+
+    // For the handle of a submitted request.
+    pub fn new_waiting(handle: PathHandle, goal: WorldIndex) -> State {
+        Self::WaitingForPath(Waiting {
+            handle,
+            goal,
+            failures: 0,
+            retry: 0.0,
+        })
+    }
+
+    // Waits for a new path, counting the
+    // searches that failed on the way here.
+    pub fn repath(&mut self, handle: PathHandle, goal: WorldIndex) {
+        let failures = match self {
+            State::Moving(moving) => moving.failures,
+            State::WaitingForPath(waiting) => waiting.failures,
+            _ => 0,
+        };
+        *self = Self::WaitingForPath(Waiting {
+            handle,
+            goal,
+            failures,
+            retry: 0.0,
+        });
     }
 
-    pub fn update(&mut self, world_width: usize, pos: &Position, vel: &mut Velocity, dt: f32) {
+    // Werfs that are lost are left moving, for
+    // the caller to ask for a new path.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        tiles: &Tiles,
+        paths: &mut PathQueue,
+        pos: &mut Position,
+        vel: &mut Velocity,
+        neighbours: &[Vec2],
+        dt: f32,
+        tuning: &Tuning,
+    ) -> Progress {
         match self {
-            State::Idle => (),
+            State::Idle | State::Scripted(_) | State::Dead(_) => Progress::Walking,
             State::Moving(moving) => {
-                let finished = moving.update(world_width, pos, vel, dt);
-                if finished {
+                let progress = moving.update(tiles, pos, vel, neighbours, dt, tuning);
+                if progress == Progress::Arrived {
                     *self = State::Idle;
                 }
+                progress
             }
+            State::WaitingForPath(waiting) => match waiting.update(tiles, paths, pos, dt) {
+                Some(path) => {
+                    *self = State::Moving(Moving {
+                        path,
+                        curr: 0,
+                        check: REPATH_INTERVAL,
+                        failures: waiting.failures,
+                    });
+                    Progress::Walking
+                }
+                None if waiting.failures >= REPATH_ATTEMPTS => {
+                    let goal = waiting.goal;
+                    *self = State::Idle;
+                    Progress::Failed(goal)
+                }
+                None => Progress::Walking,
+            },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hecs::World;
+
+    use super::*;
+    use crate::{paths::Hpa, test_util};
+
+    #[test]
+    fn failures_are_kept_until_arrival() {
+        let tiles = test_util::tiles(vec![0; 18], 6, 3);
+        let hpa = Hpa::new(&tiles, 4);
+        let mut queue = PathQueue::default();
+        let werf = World::new().spawn(());
+        let (from, goal) = (WorldIndex(0), WorldIndex(17));
+        let mut pos = Position {
+            p: steering::waypoint(from, &tiles),
+            z: 0,
+        };
+        let mut vel = Velocity { v: Vec2::ZERO };
+        let tuning = Tuning::default();
+        let mut update = |state: &mut State, queue: &mut PathQueue| {
+            state.update(&tiles, queue, &mut pos, &mut vel, &[], 0.0, &tuning)
+        };
+
+        // One short of giving up, then found.
+        let mut state = State::new_waiting(queue.submit(werf, from, goal), goal);
+        if let State::WaitingForPath(waiting) = &mut state {
+            waiting.failures = REPATH_ATTEMPTS - 1;
+        }
+        queue.process(&hpa, &tiles, Duration::ZERO);
+        assert_eq!(update(&mut state, &mut queue), Progress::Walking);
+        assert!(matches!(state, State::Moving(_)));
+
+        // Lost, and the next search fails, as
+        // its request was replaced.
+        let old = queue.submit(werf, from, goal);
+        state.repath(old, goal);
+        queue.submit(werf, from, goal);
+        assert_eq!(update(&mut state, &mut queue), Progress::Failed(goal));
+        assert!(matches!(state, State::Idle));
+    }
+}
@@ -0,0 +1,102 @@
+use std::mem;
+
+use hecs::Entity;
+
+use crate::{entities::Position, history::Change, tile::Tile};
+
+// Something that happened during a tick.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    // A werf reached the end of its path.
+    Arrived { werf: Entity, at: (i32, i32, i32) },
+    // A werf gave up on reaching the goal.
+    PathFailed { werf: Entity, goal: (i32, i32, i32) },
+    // A werf was pushed out of another one.
+    Collided { werf: Entity, at: Position },
+    // The tile of a cell changed, new sprites
+    // for the same tile don't count.
+    TileChanged(Change),
+    Dug { at: (i32, i32, i32), tile: Tile },
+    Died { werf: Entity, at: Position },
+    // The werf no longer exists, anything
+    // holding on to it should let go.
+    Despawned { werf: Entity },
+}
+
+// Anything that wants to hear about events
+// as each tick ends.
+pub trait Listener {
+    fn on_event(&mut self, event: &GameEvent);
+}
+
+// Systems send events during a tick. When it
+// ends they are handed to the listeners, and
+// can be read until the next one ends.
+#[derive(Debug, Default)]
+pub struct Events {
+    pending: Vec<GameEvent>,
+    last: Vec<GameEvent>,
+}
+
+impl Events {
+    pub fn send(&mut self, event: GameEvent) {
+        self.pending.push(event);
+    }
+
+    pub fn flush(&mut self, listeners: &mut [&mut dyn Listener]) {
+        self.last = mem::take(&mut self.pending);
+        for event in &self.last {
+            for listener in listeners.iter_mut() {
+                listener.on_event(event);
+            }
+        }
+    }
+
+    // Events of the last tick.
+    pub fn read(&self) -> &[GameEvent] {
+        &self.last
+    }
+}
+
+impl Extend<GameEvent> for Events {
+    fn extend<T: IntoIterator<Item = GameEvent>>(&mut self, events: T) {
+        self.pending.extend(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::Tile;
+
+    #[derive(Default)]
+    struct Counter(usize);
+
+    impl Listener for Counter {
+        fn on_event(&mut self, _event: &GameEvent) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn events_last_until_the_next_tick_ends() {
+        let mut events = Events::default();
+        let (mut a, mut b) = (Counter::default(), Counter::default());
+        let dug = GameEvent::Dug {
+            at: (1, 2, 0),
+            tile: Tile(1),
+        };
+
+        events.send(dug.clone());
+        events.send(dug.clone());
+        assert!(events.read().is_empty());
+
+        events.flush(&mut [&mut a, &mut b]);
+        assert_eq!(events.read(), [dug.clone(), dug]);
+        assert_eq!((a.0, b.0), (2, 2));
+
+        events.flush(&mut [&mut a]);
+        assert!(events.read().is_empty());
+        assert_eq!(a.0, 2);
+    }
+}
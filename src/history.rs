@@ -0,0 +1,250 @@
+use std::collections::VecDeque;
+
+use crate::{layer::LayerKind, tile::Tile, tiles::Tiles};
+
+// Everything needed to restore a cell exactly,
+// including whichever random variant it had.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub tile: Option<Tile>,
+    pub sprite: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    pub kind: LayerKind,
+    pub index: usize,
+    pub before: Cell,
+    pub after: Cell,
+}
+
+// Changes made together, undone together.
+#[derive(Debug, Default)]
+pub struct ChangeSet {
+    pub changes: Vec<Change>,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+}
+
+pub struct History {
+    undo: VecDeque<ChangeSet>,
+    redo: Vec<ChangeSet>,
+    // Total number of changes kept, across
+    // both undo and redo.
+    len: usize,
+    max_changes: usize,
+    // While held, edits add to one step
+    // instead of starting their own, so a
+    // brush stroke is undone as a whole.
+    held: bool,
+    // Whether the newest step is still taking
+    // edits. Changes from elsewhere get steps
+    // of their own, just before it.
+    open: bool,
+}
+
+impl History {
+    pub fn new(max_changes: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: vec![],
+            len: 0,
+            max_changes,
+            held: false,
+            open: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn hold(&mut self, held: bool) {
+        self.held = held;
+        if !held {
+            self.open = false;
+        }
+    }
+
+    // Takes whatever has changed in the tiles
+    // since the last commit and makes it one
+    // step of history.
+    pub fn commit(&mut self, tiles: &mut Tiles) {
+        let set = tiles.take_changes();
+        if set.is_empty() {
+            return;
+        }
+        self.account(&set);
+        let at = self.undo.len() - self.open as usize;
+        self.undo.insert(at, set);
+        self.trim();
+    }
+
+    // Like commit, for the editor's own changes,
+    // which go into the held step if there is
+    // one.
+    pub fn commit_edit(&mut self, tiles: &mut Tiles) {
+        let set = tiles.take_changes();
+        if set.is_empty() {
+            return;
+        }
+        self.account(&set);
+        match self.undo.back_mut() {
+            Some(newest) if self.open => newest.changes.extend(set.changes),
+            _ => self.undo.push_back(set),
+        }
+        self.open = self.held;
+        self.trim();
+    }
+
+    // New changes make the undone steps
+    // unreachable.
+    fn account(&mut self, set: &ChangeSet) {
+        self.len -= self.redo.drain(..).map(|set| set.len()).sum::<usize>();
+        self.len += set.len();
+    }
+
+    // The newest step is always kept, even if
+    // it is larger than the limit.
+    fn trim(&mut self) {
+        while self.len > self.max_changes && self.undo.len() > 1 {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.len -= oldest.len();
+            }
+        }
+    }
+
+    pub fn undo(&mut self, tiles: &mut Tiles) -> bool {
+        // Uncommitted changes would otherwise
+        // end up in the wrong step.
+        self.commit(tiles);
+
+        let Some(set) = self.undo.pop_back() else {
+            return false;
+        };
+        self.open = false;
+        tiles.revert(&set);
+        self.redo.push(set);
+        true
+    }
+
+    pub fn redo(&mut self, tiles: &mut Tiles) -> bool {
+        let Some(set) = self.redo.pop() else {
+            return false;
+        };
+        self.open = false;
+        tiles.reapply(&set);
+        self.undo.push_back(set);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::test_util;
+
+    fn tiles() -> Tiles {
+        test_util::tiles(vec![1, 1, 1, 0, 0, 0, 1, 1, 1], 3, 3)
+    }
+
+    fn snapshot(tiles: &Tiles) -> Vec<Cell> {
+        LayerKind::ALL
+            .into_iter()
+            .flat_map(|kind| (0..tiles.len()).map(move |i| tiles.cell(kind, i)))
+            .collect()
+    }
+
+    #[test]
+    fn undo_and_redo_restore_cells_exactly() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tiles = tiles();
+        let mut history = History::new(100);
+        let before = snapshot(&tiles);
+
+        tiles.set_square(&mut rng, 0, 0, 0, 2, Tile(0));
+        history.commit(&mut tiles);
+        let after = snapshot(&tiles);
+
+        assert!(history.undo(&mut tiles));
+        assert_eq!(snapshot(&tiles), before);
+        assert!(history.redo(&mut tiles));
+        assert_eq!(snapshot(&tiles), after);
+    }
+
+    #[test]
+    fn oldest_steps_are_dropped() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tiles = tiles();
+        let mut history = History::new(1);
+
+        tiles.set_tile(&mut rng, 0, Tile(0));
+        history.commit(&mut tiles);
+        tiles.set_tile(&mut rng, 8, Tile(0));
+        history.commit(&mut tiles);
+
+        assert!(history.undo(&mut tiles));
+        assert!(!history.undo(&mut tiles));
+    }
+
+    #[test]
+    fn held_commits_are_one_step() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tiles = tiles();
+        let mut history = History::new(100);
+        let before = snapshot(&tiles);
+
+        history.hold(true);
+        tiles.set_tile(&mut rng, 0, Tile(0));
+        history.commit_edit(&mut tiles);
+        tiles.set_tile(&mut rng, 8, Tile(0));
+        history.commit_edit(&mut tiles);
+        history.hold(false);
+
+        assert!(history.undo(&mut tiles));
+        assert_eq!(snapshot(&tiles), before);
+        assert!(!history.undo(&mut tiles));
+    }
+
+    #[test]
+    fn other_changes_stay_out_of_held_steps() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tiles = tiles();
+        let mut history = History::new(100);
+        let before = snapshot(&tiles);
+        let top = |tiles: &Tiles| (0..9).map(|i| tiles.top_tile(i)).collect::<Vec<_>>();
+
+        history.hold(true);
+        tiles.set_tile(&mut rng, 0, Tile(0));
+        history.commit_edit(&mut tiles);
+        // Dug by a script mid-stroke.
+        tiles.dig(&mut rng, 1);
+        history.commit(&mut tiles);
+        tiles.set_tile(&mut rng, 8, Tile(0));
+        history.commit_edit(&mut tiles);
+        history.hold(false);
+
+        // Only the stroke is undone first.
+        assert!(history.undo(&mut tiles));
+        let mut dug = top(&self::tiles());
+        dug[1] = Some(Tile(0));
+        assert_eq!(top(&tiles), dug);
+        assert!(history.undo(&mut tiles));
+        assert_eq!(snapshot(&tiles), before);
+        assert!(!history.undo(&mut tiles));
+    }
+}
@@ -0,0 +1,70 @@
+use serde::Deserialize;
+
+use crate::tile::Tile;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerKind {
+    #[default]
+    Floor,
+    // Walls and anything else that decides
+    // whether a tile can be walked through.
+    Structure,
+    Decoration,
+}
+
+impl LayerKind {
+    // In drawing order.
+    pub const ALL: [LayerKind; 3] = [
+        LayerKind::Floor,
+        LayerKind::Structure,
+        LayerKind::Decoration,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LayerKind::Floor => "floor",
+            LayerKind::Structure => "structure",
+            LayerKind::Decoration => "decoration",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Layer {
+    pub tiles: Vec<Option<Tile>>,
+    // Tilesheet index drawn for each tile,
+    // picked when the tile or one of its
+    // neighbours changes.
+    pub sprites: Vec<u8>,
+}
+
+impl Layer {
+    pub fn new(len: usize) -> Self {
+        Self {
+            tiles: vec![None; len],
+            sprites: vec![0; len],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_are_found_by_name() {
+        for kind in LayerKind::ALL {
+            assert_eq!(LayerKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(LayerKind::from_name("roof"), None);
+        // Tiles keeps layers in this order.
+        for (i, kind) in LayerKind::ALL.into_iter().enumerate() {
+            assert_eq!(kind as usize, i);
+        }
+    }
+}
@@ -1,49 +1,349 @@
-use std::{fs::read_to_string, io};
+use std::{
+    fs::{read_to_string, write},
+    io,
+};
 
-use ::rand::rngs::ThreadRng;
+use ::rand::rngs::StdRng;
 use macroquad::prelude::*;
 
-use crate::tiles::Tiles;
+use crate::{
+    assets::{Manifest, Sheet, TILESET},
+    autotile::Autotile,
+    cave,
+    constants::TILE_SIZE,
+    entities::Position,
+    palette,
+    tile::TileRegistry,
+    tiled::{self, Zone},
+    tiles::Tiles,
+};
 
 #[derive(Debug)]
 pub struct Level {
     pub tiles: Tiles,
+    // File the level was loaded from.
+    pub path: Option<String>,
+    // Where werfs are placed, in world coordinates.
+    // Hand-written levels don't define any.
+    pub spawns: Vec<Position>,
+    pub zones: Vec<Zone>,
 }
 
 impl Level {
-    pub fn new(rng: &mut ThreadRng, path: &str) -> io::Result<Self> {
-        let (tiles, width) = load_level(path)?;
+    // Tiled maps and PNGs are picked by
+    // extension, anything else is read as digits.
+    pub fn new(rng: &mut StdRng, path: &str, manifest: &Manifest) -> io::Result<Self> {
+        if tiled::is_tiled(path) {
+            return Self::import_tiled(rng, path, manifest);
+        }
+        if path.ends_with(".png") {
+            return Self::import_png(rng, path, manifest);
+        }
+
+        let (tiles, width, height) = load_level(path)?;
+        let (registry, autotile) = load_data(manifest)?;
+
+        Ok(Self {
+            tiles: Tiles::new(rng, tiles, width, height, registry, autotile)?,
+            path: Some(path.to_string()),
+            spawns: vec![],
+            zones: vec![],
+        })
+    }
+
+    fn import_tiled(rng: &mut StdRng, path: &str, manifest: &Manifest) -> io::Result<Self> {
+        let (registry, autotile) = load_data(manifest)?;
+        let map = tiled::load(path, &registry)?;
+
+        Ok(Self {
+            tiles: Tiles::from_layers(rng, map.layers, map.width, map.height, registry, autotile)?,
+            path: Some(path.to_string()),
+            spawns: map.spawns,
+            zones: map.zones,
+        })
+    }
+
+    fn import_png(rng: &mut StdRng, path: &str, manifest: &Manifest) -> io::Result<Self> {
+        let (registry, autotile) = load_data(manifest)?;
+        let image = palette::load_png(path, &registry, &manifest.palette)?;
 
         Ok(Self {
-            tiles: Tiles::new(rng, tiles, width),
+            tiles: Tiles::new(
+                rng,
+                image.tiles,
+                image.width,
+                image.height,
+                registry,
+                autotile,
+            )?,
+            path: Some(path.to_string()),
+            spawns: image.spawns,
+            zones: vec![],
         })
     }
 
-    pub fn draw(&self, texture: &Texture2D) {
-        self.tiles.draw(texture);
+    pub fn generate(
+        rng: &mut StdRng,
+        seed: u64,
+        width: usize,
+        height: usize,
+        manifest: &Manifest,
+    ) -> io::Result<Self> {
+        let cave = cave::generate(seed, width, height);
+        let (x, y) = cave.spawn;
+        let (registry, autotile) = load_data(manifest)?;
+
+        Ok(Self {
+            tiles: Tiles::new(rng, cave.tiles, cave.width, height, registry, autotile)?,
+            path: None,
+            spawns: vec![Position {
+                p: vec2(x as f32, y as f32) * TILE_SIZE,
+                z: 0,
+            }],
+            zones: vec![],
+        })
+    }
+
+    // Reads the level file again, for when it
+    // has changed on disk. Generated levels
+    // have nothing to reload.
+    pub fn reload(&mut self, rng: &mut StdRng, manifest: &Manifest) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        *self = Self::new(rng, path, manifest)?;
+        Ok(())
+    }
+
+    // Saving writes digits, which should not
+    // overwrite a Tiled map or an image.
+    pub fn save_path(&self) -> Option<&str> {
+        self.path
+            .as_deref()
+            .filter(|path| !tiled::is_tiled(path) && !path.ends_with(".png"))
+    }
+
+    // Writes the level in the same format
+    // load_level reads. Only one tile fits
+    // per cell, decorations are dropped.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        write(path, self.to_text()?)
+    }
+
+    fn to_text(&self) -> io::Result<String> {
+        let tiles = &self.tiles;
+        let open = tiles.registry.open_tile();
+        let level_len = tiles.width * tiles.height;
+
+        let mut out = String::new();
+        for z in 0..tiles.depth {
+            if z > 0 {
+                out.push('\n');
+            }
+            for row in 0..tiles.height {
+                let start = z * level_len + row * tiles.width;
+                let ids = (start..start + tiles.width)
+                    .map(|i| match tiles.top_tile(i).or(open) {
+                        Some(t) => Ok(format!("{:02}", t.0)),
+                        None => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "open cell without an open tile type",
+                        )),
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                out.push_str(&ids.join(" "));
+                out.push('\n');
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn draw(&self, sheet: &Sheet, z: usize) {
+        self.tiles.draw(sheet, z);
+
+        for zone in self.zones.iter().filter(|zone| zone.z == z) {
+            let r = zone.rect;
+            draw_rectangle_lines(r.x, r.y, r.w, r.h, 1.0, SKYBLUE);
+            draw_text(&zone.name, r.x + 2.0, r.y + 6.0, 8.0, SKYBLUE);
+        }
     }
+}
 
-    pub fn width(&self) -> usize {
-        self.tiles.width
+// The autotile mapping is optional, without
+// it walls only distinguish top and side.
+fn load_data(manifest: &Manifest) -> io::Result<(TileRegistry, Autotile)> {
+    let sheet = manifest.sheet(TILESET);
+    let columns = sheet.columns;
+    let registry = TileRegistry::load(&manifest.tiles, columns, sheet.rows)?;
+    if !manifest.autotile.exists() {
+        return Ok((registry, Autotile::new(columns, sheet.rows)?));
     }
+    Ok((
+        registry,
+        Autotile::load(&manifest.autotile, columns, sheet.rows)?,
+    ))
 }
 
-fn load_level(path: &str) -> io::Result<(Vec<u8>, usize)> {
+// Z-levels are separated by an empty line,
+// starting with the bottom one.
+fn load_level(path: &str) -> io::Result<(Vec<u8>, usize, usize)> {
+    parse_level(&read_to_string(path)?)
+}
+
+// Tile ids divided by spaces. Every row has
+// to be as wide as the first, and every
+// level as high.
+fn parse_level(s: &str) -> io::Result<(Vec<u8>, usize, usize)> {
+    let invalid = |line: usize, message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line + 1, message),
+        )
+    };
+
+    let mut tiles = vec![];
     let mut width = 0;
-    let tiles = read_to_string(path)?
-        .lines()
-        .flat_map(|line| {
-            if width == 0 {
-                // Digits are 2 characters wide,
-                // divided by spaces, no space
-                // at the end.
-                width = (line.len() / 3) + 1;
+    // Rows of each level.
+    let mut heights = vec![0];
+    for (n, line) in s.lines().enumerate() {
+        if line.trim().is_empty() {
+            if *heights.last().unwrap() > 0 {
+                heights.push(0);
             }
-            line.split(" ")
-                .map(|c| c.parse::<u8>().unwrap_or(0))
-                .collect::<Vec<u8>>()
-        })
-        .collect::<Vec<u8>>();
+            continue;
+        }
+
+        let row = line
+            .split_whitespace()
+            .map(|cell| {
+                cell.parse::<u8>()
+                    .map_err(|_| invalid(n, format!("{} is not a tile id", cell)))
+            })
+            .collect::<io::Result<Vec<u8>>>()?;
+        if width == 0 {
+            width = row.len();
+        } else if row.len() != width {
+            return Err(invalid(
+                n,
+                format!("{} tiles wide, the first row is {}", row.len(), width),
+            ));
+        }
+        tiles.extend(row);
+        *heights.last_mut().unwrap() += 1;
+    }
+
+    if heights.last() == Some(&0) {
+        heights.pop();
+    }
+    let height = heights.first().copied().unwrap_or(0);
+    if let Some(z) = heights.iter().position(|&h| h != height) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "level {} has {} rows, the first one has {}",
+                z, heights[z], height
+            ),
+        ));
+    }
+
+    Ok((tiles, width, height))
+}
 
-    Ok((tiles, width))
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use ::rand::SeedableRng;
+
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn saved_levels_load_the_same() {
+        let registry = TileRegistry::parse(
+            r#"
+            [[tile]]
+            id = 0
+            name = "ground"
+            sheet = [0, 0]
+
+            [[tile]]
+            id = 120
+            name = "wall"
+            sheet = [1, 0]
+            layer = "structure"
+
+            [[tile]]
+            id = 8
+            name = "open"
+            sheet = [0, 0]
+            open = true
+            "#,
+            16,
+            16,
+        )
+        .unwrap();
+        let grid = vec![0, 120, 0, 120, 0, 0, 8, 8, 120, 8, 0, 8];
+        let tiles = Tiles::new(
+            &mut StdRng::seed_from_u64(0),
+            grid.clone(),
+            3,
+            2,
+            registry,
+            Autotile::new(16, 16).unwrap(),
+        )
+        .unwrap();
+        let level = Level {
+            tiles,
+            path: None,
+            spawns: vec![],
+            zones: vec![],
+        };
+
+        let text = level.to_text().unwrap();
+        assert_eq!(text, "00 120 00\n120 00 00\n\n08 08 120\n08 00 08\n");
+        assert_eq!(parse_level(&text).unwrap(), (grid, 3, 2));
+    }
+
+    #[test]
+    fn painted_holes_stay_open() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut level = Level {
+            tiles: test_util::tiles(vec![0, 1, 0, 0], 2, 2),
+            path: None,
+            spawns: vec![],
+            zones: vec![],
+        };
+        let open = level.tiles.registry.by_name("open").unwrap();
+        level.tiles.set_tile(&mut rng, 0, open);
+        level.tiles.set_tile(&mut rng, 1, open);
+
+        let path = env::temp_dir().join(format!("werfs_level_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        level.save(path).unwrap();
+        let (grid, width, height) = load_level(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let reg = test_util::registry();
+        let autotile = Autotile::new(16, 16).unwrap();
+        let loaded = Tiles::new(&mut rng, grid, width, height, reg, autotile).unwrap();
+        for i in 0..4 {
+            assert_eq!(loaded.is_open(i), level.tiles.is_open(i), "{}", i);
+        }
+        assert!(loaded.is_open(0) && loaded.is_open(1));
+    }
+
+    #[test]
+    fn bad_levels_are_rejected() {
+        for (s, expected) in [
+            ("00 01\n00 xx\n", "line 2: xx is not a tile id"),
+            ("00 01\n00 256\n", "line 2: 256 is not a tile id"),
+            ("00 01\n00\n", "line 2: 1 tiles wide"),
+            ("00 01\n00 01\n\n00 01\n", "level 1 has 1 rows"),
+        ] {
+            let err = parse_level(s).expect_err(s).to_string();
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
 }
@@ -0,0 +1,33 @@
+pub mod ascii;
+pub mod assets;
+pub mod autotile;
+pub mod benchmark;
+pub mod camera;
+pub mod cave;
+pub mod cli;
+pub mod console;
+pub mod constants;
+pub mod editor;
+pub mod entities;
+pub mod events;
+pub mod history;
+pub mod layer;
+pub mod level;
+pub mod overlay;
+pub mod palette;
+pub mod paths;
+pub mod placeholder;
+pub mod reload;
+pub mod script;
+pub mod sim;
+pub mod spawn;
+pub mod stats;
+pub mod steering;
+pub mod steps;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod tile;
+pub mod tiled;
+pub mod tiles;
+pub mod tuning;
+pub mod utils;
@@ -1,80 +1,288 @@
-use ::rand::thread_rng;
-use constants::TILE_SIZE;
-use entities::Position;
-use hecs::World;
-use level::Level;
-use macroquad::prelude::*;
-use utils::v2_to_index;
-
-mod camera;
-mod constants;
-mod entities;
-mod level;
-mod resources;
-mod spawn;
-mod steps;
-mod tile;
-mod tiles;
-mod utils;
-
-#[macroquad::main("WERFS")]
-async fn main() {
-    let (werfs_texture, tileset_texture) = resources::load().await;
-
-    let mut rng = thread_rng();
-
-    let mut world = World::new();
-
-    let level_path = "../resources/level_debug";
-    let Ok(mut level) = Level::new(&mut rng, level_path) else {
-        panic!("failed to load {}", level_path);
+use std::{
+    env, process,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use ::rand::{thread_rng, Rng};
+use macroquad::{prelude::*, Window};
+use mq_evaluation::{
+    ascii,
+    assets::{Assets, Manifest, TILESET, WERFS},
+    benchmark, camera,
+    cli::{self, Command, Mode},
+    console::{self, Console},
+    constants::{CLUSTER_SIZE, HISTORY_CHANGES, RELOAD_INTERVAL, TICK, TILE_SIZE},
+    editor::Editor,
+    entities::WorldIndex,
+    history::History,
+    level::Level,
+    overlay::Overlay,
+    paths::Hpa,
+    reload::{Watched, Watcher},
+    script::{Message, Scripts},
+    sim::Simulation,
+    steps,
+    tile::Tile,
+    tiles::Tiles,
+    tuning::{Panel, Tuning},
+};
+
+fn main() {
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
     };
 
-    let mut cam = camera::Camera::new(Vec2 {
-        x: screen_width() / 5.0,
-        y: screen_height() / 5.0,
-    });
+    let manifest = match Manifest::load(options.assets.clone()) {
+        Ok(manifest) => manifest,
+        Err(err) => fail(format!("failed to load asset manifest: {}", err)),
+    };
 
-    let mut total_werfs = 0;
+    let seed = options.seed.unwrap_or_else(|| thread_rng().gen());
+    println!("seed {}", seed);
+    let mut sim = match Simulation::new(&options, &manifest, seed) {
+        Ok(sim) => sim,
+        Err(err) => fail(err.to_string()),
+    };
 
-    let first_entity = spawn::two_werfs(&mut total_werfs, &mut world, &mut rng);
+    match options.mode {
+        Mode::Interactive => {
+            let (width, height) = options.window;
+            let conf = Conf {
+                window_title: "WERFS".to_string(),
+                window_width: width,
+                window_height: height,
+                ..Default::default()
+            };
+            Window::from_config(conf, interactive(manifest, sim));
+        }
+        Mode::Headless => {
+            if let Some(path) = &options.script {
+                let z = sim.spawn.z;
+                if let Err(err) = console::run_script(&mut sim, path, z) {
+                    fail(err.to_string());
+                }
+            }
+            for tick in 0..options.ticks {
+                let start = Instant::now();
+                sim.tick();
+                console::print_messages(&sim);
 
-    let mut elapsed = get_time();
+                let Some(every) = options.view else {
+                    continue;
+                };
+                if tick % every == 0 {
+                    // Clears the terminal first.
+                    let view = ascii::render(&sim.level.tiles, &sim.world, sim.spawn.z);
+                    print!("\x1b[H\x1b[2J{}", view);
+                }
+                // Otherwise it's over before it can
+                // be followed.
+                sleep(Duration::from_secs_f32(TICK).saturating_sub(start.elapsed()));
+            }
+            println!(
+                "ran {} ticks with {} werfs",
+                options.ticks,
+                sim.population()
+            );
+        }
+        Mode::Benchmark => print!("{}", benchmark::run(&mut sim, &options, seed)),
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+async fn interactive(manifest: Manifest, mut sim: Simulation) {
+    let mut assets = Assets::load(&manifest).await;
+
+    let mut cam = camera::Camera::new(sim.spawn.p);
+    cam.z = sim.spawn.z;
+
+    let wall = sim.level.tiles.registry.by_name("wall").unwrap_or(Tile(0));
+    let mut editor = Editor::new(wall);
+    let mut overlay = Overlay::default();
+    let mut console = Console::default();
+    let mut panel = Panel::default();
+    // Shown until the console is opened.
+    let mut script_error = None;
+
+    let mut watcher = watch_files(&manifest, &sim.level);
+
+    let mut reload_elapsed = get_time();
 
     loop {
         let dt = get_frame_time();
 
         clear_background(BLACK);
 
-        let mut positions: Vec<Position> = Vec::with_capacity(total_werfs as usize);
+        if !sim.paused {
+            sim.update(dt);
+        }
 
         cam.set_cam(None);
 
-        level.draw(&tileset_texture);
+        sim.level.draw(&assets.tileset, cam.z);
+        steps::draw(&mut sim.world, &assets.werfs, cam.z);
 
-        steps::move_and_draw(&mut world, &werfs_texture, &mut positions);
+        overlay.draw(
+            &sim.level.tiles,
+            &sim.world,
+            sim.events.read(),
+            cam.z,
+            cam.view(),
+        );
 
-        steps::collision(&mut world, positions);
+        editor.draw_cursor(&sim.level.tiles, cam.mpos);
 
-        steps::state(&mut world, level.width(), dt);
+        let pos = sim
+            .controlled
+            .and_then(|werf| steps::position_for(&mut sim.world, werf));
 
-        if get_time() - elapsed > 0.128 {
-            elapsed = get_time();
-            steps::animation(&mut world);
+        cam.set_default_cam();
+        console.update(&mut sim, cam.z);
+        for message in sim.scripts.take_messages() {
+            match message {
+                Message::Log(line) => console.push_log(line),
+                Message::Error(err) => {
+                    console.push_log(format!("error: {}", err));
+                    script_error = Some(err);
+                }
+            }
+        }
+        if console.open {
+            script_error = None;
         }
+        // Typing should not move the camera or
+        // paint tiles.
+        if !console.open {
+            cam.update(
+                dt,
+                &mut sim.world,
+                sim.controlled,
+                pos,
+                &sim.level.tiles,
+                &mut sim.requests,
+                editor.enabled,
+            );
 
-        let pos = steps::position_for(&mut world, first_entity);
+            if editor.update(
+                &mut sim.rng,
+                &mut sim.level,
+                &mut sim.history,
+                cam.mpos,
+                cam.z,
+            ) {
+                watcher.refresh();
+            }
 
-        cam.set_default_cam();
-        cam.update(dt, &mut world, first_entity, pos, &mut level.tiles);
+            overlay.update();
+
+            if panel.update(&mut sim.tuning, &manifest.tuning) {
+                watcher.refresh();
+            }
+        }
 
-        draw_debug_info(total_werfs, cam.mpos, level.width());
+        if get_time() - reload_elapsed > RELOAD_INTERVAL {
+            reload_elapsed = get_time();
+            for watched in watcher.changed() {
+                match watched {
+                    Watched::Sheet(name) => assets.reload(&manifest, &name).await,
+                    Watched::Tuning => reload_tuning(&manifest, &mut sim.tuning),
+                    Watched::Scripts => {
+                        reload_scripts(&manifest, &mut sim);
+                        // Scripts may have been added or
+                        // removed.
+                        watcher = watch_files(&manifest, &sim.level);
+                    }
+                    Watched::Level => {
+                        reload_level(&manifest, &mut sim);
+                        // Reloads don't send tile changes.
+                        sim.paths = Hpa::new(&sim.level.tiles, CLUSTER_SIZE);
+                        sim.history = History::new(HISTORY_CHANGES);
+                        cam.z = cam.z.min(sim.level.tiles.depth - 1);
+                        if !sim.level.tiles.registry.contains(editor.tile.0) {
+                            editor.tile = wall;
+                        }
+                    }
+                }
+            }
+        }
+
+        draw_debug_info(sim.population(), cam.mpos, cam.z, &sim.level.tiles);
+        editor.draw_hud(&sim.level.tiles);
+        overlay.draw_hud();
+        if let Some(err) = &script_error {
+            draw_text(format!("SCRIPT: {}", err).as_str(), 16.0, 80.0, 16.0, RED);
+        }
+        panel.draw(&sim.tuning);
+        console.draw();
 
         next_frame().await
     }
 }
 
-fn draw_debug_info(total_werfs: i32, mouse_pos: Vec2, world_width: usize) {
+// Textures, tuning, scripts, the level file
+// and the data files it's built from.
+fn watch_files(manifest: &Manifest, level: &Level) -> Watcher {
+    let mut watcher = Watcher::default();
+    watcher.watch(&manifest.tuning, Watched::Tuning);
+    // The directory changes when scripts are
+    // added or removed.
+    watcher.watch(&manifest.scripts, Watched::Scripts);
+    for path in Scripts::paths(&manifest.scripts).unwrap_or_default() {
+        watcher.watch(path, Watched::Scripts);
+    }
+    for name in [WERFS, TILESET] {
+        let path = manifest.resolve(&manifest.sheet(name).path);
+        watcher.watch(path, Watched::Sheet(name.to_string()));
+    }
+    if let Some(path) = &level.path {
+        watcher.watch(path, Watched::Level);
+        for path in [&manifest.tiles, &manifest.autotile, &manifest.palette] {
+            watcher.watch(path, Watched::Level);
+        }
+    }
+    watcher
+}
+
+// A broken file keeps the current level, it
+// is probably being edited.
+fn reload_level(manifest: &Manifest, sim: &mut Simulation) {
+    match sim.level.reload(&mut sim.rng, manifest) {
+        Ok(()) => steps::relocate(&mut sim.world, &sim.level.tiles, &mut sim.requests),
+        Err(err) => eprintln!("warning: failed to reload level: {}", err),
+    }
+}
+
+// Starts over, what the old scripts kept in
+// Lua is gone. Werfs keep their behaviours
+// by name.
+fn reload_scripts(manifest: &Manifest, sim: &mut Simulation) {
+    match Scripts::load(&manifest.scripts, sim.seed, &sim.level.tiles) {
+        Ok(loaded) => sim.scripts = loaded,
+        Err(err) => eprintln!("warning: failed to reload scripts: {}", err),
+    }
+}
+
+fn reload_tuning(manifest: &Manifest, tuning: &mut Tuning) {
+    match Tuning::load(&manifest.tuning) {
+        Ok(loaded) => *tuning = loaded,
+        Err(err) => eprintln!("warning: failed to reload tuning: {}", err),
+    }
+}
+
+fn draw_debug_info(population: usize, mouse_pos: Vec2, z: usize, tiles: &Tiles) {
     macroquad_profiler::profiler(macroquad_profiler::ProfilerParams {
         fps_counter_pos: Vec2 {
             x: 16.0,
@@ -83,7 +291,7 @@ fn draw_debug_info(total_werfs: i32, mouse_pos: Vec2, world_width: usize) {
     });
 
     draw_text(
-        format!("WERFS: {}", total_werfs).as_str(),
+        format!("WERFS: {}", population).as_str(),
         16.0,
         16.0,
         16.0,
@@ -92,10 +300,11 @@ fn draw_debug_info(total_werfs: i32, mouse_pos: Vec2, world_width: usize) {
 
     draw_text(
         format!(
-            "MOUSE: {{x: {}, y: {}}}, {}, {{x: {}, y: {}}}",
+            "MOUSE: {{x: {}, y: {}, z: {}}}, {}, {{x: {}, y: {}}}",
             mouse_pos.x as i32,
             mouse_pos.y as i32,
-            v2_to_index(mouse_pos, world_width),
+            z,
+            WorldIndex::new(mouse_pos.x as i32, mouse_pos.y as i32, z as i32, tiles).0,
             (mouse_pos.x * TILE_SIZE) as i32,
             (mouse_pos.y * TILE_SIZE) as i32,
         )
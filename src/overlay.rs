@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+
+use hecs::World;
+use macroquad::prelude::*;
+
+use crate::{
+    constants::{COLLISION_RADIUS, TILE_SIZE},
+    entities::{Position, State, Velocity},
+    events::GameEvent,
+    tiles::Tiles,
+};
+
+// Above this many visible tiles, indices
+// would be too small to read anyway.
+const MAX_INDEX_LABELS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layer {
+    Paths,
+    Collision,
+    Passability,
+    Indices,
+    Velocity,
+}
+
+impl Layer {
+    // Toggled with F1 to F5, in this order.
+    pub const ALL: [Layer; 5] = [
+        Layer::Paths,
+        Layer::Collision,
+        Layer::Passability,
+        Layer::Indices,
+        Layer::Velocity,
+    ];
+
+    const KEYS: [KeyCode; 5] = [
+        KeyCode::F1,
+        KeyCode::F2,
+        KeyCode::F3,
+        KeyCode::F4,
+        KeyCode::F5,
+    ];
+}
+
+// Debug drawing on top of the world. Only
+// ever reads the game state.
+#[derive(Debug, Default)]
+pub struct Overlay {
+    enabled: [bool; Layer::ALL.len()],
+}
+
+impl Overlay {
+    pub fn is_enabled(&self, layer: Layer) -> bool {
+        self.enabled[layer as usize]
+    }
+
+    pub fn toggle(&mut self, layer: Layer) {
+        self.enabled[layer as usize] = !self.enabled[layer as usize];
+    }
+
+    pub fn update(&mut self) {
+        for (layer, key) in Layer::ALL.into_iter().zip(Layer::KEYS) {
+            if is_key_pressed(key) {
+                self.toggle(layer);
+            }
+        }
+    }
+
+    // In world space, for level z. Tile layers
+    // only cover what's in view.
+    pub fn draw(&self, tiles: &Tiles, world: &World, events: &[GameEvent], z: usize, view: Rect) {
+        let x0 = (view.x / TILE_SIZE).floor().max(0.0) as i32;
+        let y0 = (view.y / TILE_SIZE).floor().max(0.0) as i32;
+        let x1 = ((view.right() / TILE_SIZE).ceil() as i32).min(tiles.width as i32);
+        let y1 = ((view.bottom() / TILE_SIZE).ceil() as i32).min(tiles.height as i32);
+        let visible = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+            .filter_map(|(x, y)| Some((x, y, tiles.index_at(x, y, z as i32)?)));
+
+        if self.is_enabled(Layer::Passability) {
+            for (x, y, i) in visible.clone() {
+                if tiles.is_blocked(i) {
+                    let (x, y) = (x as f32 * TILE_SIZE, y as f32 * TILE_SIZE);
+                    draw_rectangle(x, y, TILE_SIZE, TILE_SIZE, Color::new(1.0, 0.0, 0.0, 0.3));
+                }
+            }
+        }
+
+        let shown = ((x1 - x0).max(0) * (y1 - y0).max(0)) as usize;
+        if self.is_enabled(Layer::Indices) && shown <= MAX_INDEX_LABELS {
+            for (x, y, i) in visible {
+                let (x, y) = (x as f32 * TILE_SIZE, y as f32 * TILE_SIZE);
+                draw_text(&i.to_string(), x + 1.0, y + 5.0, 6.0, WHITE);
+            }
+        }
+
+        if self.is_enabled(Layer::Paths) {
+            draw_paths(tiles, world, z);
+        }
+
+        if self.is_enabled(Layer::Collision) {
+            draw_collision(world, events, z);
+        }
+
+        if self.is_enabled(Layer::Velocity) {
+            for (_id, (pos, vel)) in world.query::<(&Position, &Velocity)>().iter() {
+                if pos.z == z {
+                    let from = pos.p + COLLISION_RADIUS;
+                    // Per frame, too short to see
+                    // otherwise.
+                    let to = from + vel.v * 8.0;
+                    draw_line(from.x, from.y, to.x, to.y, 0.5, SKYBLUE);
+                }
+            }
+        }
+    }
+
+    pub fn draw_hud(&self) {
+        let enabled = Layer::ALL
+            .into_iter()
+            .filter(|&layer| self.is_enabled(layer))
+            .map(|layer| format!("{:?}", layer))
+            .collect::<Vec<_>>();
+        if enabled.is_empty() {
+            return;
+        }
+
+        draw_text(
+            format!("OVERLAY: {}", enabled.join(", ")).as_str(),
+            16.0,
+            64.0,
+            16.0,
+            ORANGE,
+        );
+    }
+}
+
+// The waypoints left, starting at the werf.
+fn draw_paths(tiles: &Tiles, world: &World, z: usize) {
+    let centre = |p: Vec2| p + TILE_SIZE / 2.0;
+
+    for (_id, (pos, state)) in world.query::<(&Position, &State)>().iter() {
+        let State::Moving(moving) = state else {
+            continue;
+        };
+
+        let mut from = (pos.z == z).then_some(pos.p + COLLISION_RADIUS);
+        for index in moving.remaining() {
+            let p = centre(index.to_vec(tiles) * TILE_SIZE);
+            let on_z = index.z(tiles) == z;
+            if on_z {
+                draw_circle(p.x, p.y, 1.0, YELLOW);
+                if let Some(from) = from {
+                    draw_line(from.x, from.y, p.x, p.y, 0.5, YELLOW);
+                }
+            }
+            from = on_z.then_some(p);
+        }
+    }
+}
+
+// Red for werfs that collided last tick.
+fn draw_collision(world: &World, events: &[GameEvent], z: usize) {
+    let colliding = events
+        .iter()
+        .filter_map(|event| match event {
+            GameEvent::Collided { werf, .. } => Some(*werf),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    for (id, pos) in world.query::<&Position>().iter() {
+        if pos.z != z {
+            continue;
+        }
+        let colliding = colliding.contains(&id);
+        draw_circle_lines(
+            pos.p.x + COLLISION_RADIUS,
+            pos.p.y + COLLISION_RADIUS,
+            COLLISION_RADIUS,
+            1.4,
+            if colliding { RED } else { GREEN },
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashMap,
+    fs::{read, read_to_string},
+    io,
+    path::{Path, PathBuf},
+};
+
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    constants::TILE_SIZE,
+    entities::Position,
+    tile::{Tile, TileRegistry},
+    tiles::DEFAULT_FLOOR,
+};
+
+const SPAWN: &str = "spawn";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entry {
+    Tile(Tile),
+    // A werf spawn point, on the default floor.
+    Spawn,
+}
+
+#[derive(Deserialize)]
+struct PaletteFile {
+    colours: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct Palette {
+    // Keyed by rgba.
+    entries: HashMap<[u8; 4], Entry>,
+}
+
+impl Palette {
+    pub fn load(path: impl AsRef<Path>, registry: &TileRegistry) -> io::Result<Self> {
+        Self::parse(&read_to_string(path)?, registry)
+    }
+
+    pub fn parse(s: &str, registry: &TileRegistry) -> io::Result<Self> {
+        let file: PaletteFile =
+            toml::from_str(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let mut entries = HashMap::new();
+        for (colour, name) in file.colours {
+            let entry = match name.as_str() {
+                SPAWN => Entry::Spawn,
+                name => Entry::Tile(registry.by_name(name).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("palette colour {} uses unknown tile {}", colour, name),
+                    )
+                })?),
+            };
+            entries.insert(parse_colour(&colour)?, entry);
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn get(&self, rgba: [u8; 4]) -> Option<Entry> {
+        self.entries.get(&rgba).copied()
+    }
+}
+
+// Either rrggbb or rrggbbaa, with or
+// without a leading #.
+fn parse_colour(s: &str) -> io::Result<[u8; 4]> {
+    let hex = s.trim_start_matches('#');
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid palette colour {}", s),
+        )
+    };
+
+    if !(hex.len() == 6 || hex.len() == 8) {
+        return Err(invalid());
+    }
+
+    let mut rgba = [0, 0, 0, 255];
+    for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+        *channel = hex
+            .get(i * 2..i * 2 + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(invalid)?;
+    }
+    Ok(rgba)
+}
+
+pub struct Imported {
+    pub tiles: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub spawns: Vec<Position>,
+}
+
+// One pixel per tile, single z-level.
+pub fn load_png(path: &str, registry: &TileRegistry, default: &Path) -> io::Result<Imported> {
+    let palette = Palette::load(palette_path(path, default), registry)?;
+    let image = Image::from_file_with_format(&read(path)?, Some(ImageFormat::Png))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+
+    from_image(&image, &palette, registry)
+}
+
+// level.png looks for level.palette.toml
+// before falling back to the default.
+fn palette_path(path: &str, default: &Path) -> PathBuf {
+    let own = Path::new(path).with_extension("palette.toml");
+    if own.exists() {
+        return own;
+    }
+    default.to_path_buf()
+}
+
+pub fn from_image(
+    image: &Image,
+    palette: &Palette,
+    registry: &TileRegistry,
+) -> io::Result<Imported> {
+    let (width, height) = (image.width(), image.height());
+    let floor = registry.by_name(DEFAULT_FLOOR).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("missing default floor tile {}", DEFAULT_FLOOR),
+        )
+    })?;
+
+    let mut tiles = Vec::with_capacity(width * height);
+    let mut spawns = vec![];
+
+    for (i, pixel) in image.bytes.chunks_exact(4).enumerate() {
+        let rgba = [pixel[0], pixel[1], pixel[2], pixel[3]];
+        let (x, y) = (i % width, i / width);
+        match palette.get(rgba) {
+            Some(Entry::Tile(t)) => tiles.push(t.0),
+            Some(Entry::Spawn) => {
+                tiles.push(floor.0);
+                spawns.push(Position {
+                    p: vec2(x as f32, y as f32) * TILE_SIZE,
+                    z: 0,
+                });
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                    "pixel {},{} has colour {:02x}{:02x}{:02x}{:02x}, which is not in the palette",
+                    x, y, rgba[0], rgba[1], rgba[2], rgba[3]
+                ),
+                ))
+            }
+        }
+    }
+
+    Ok(Imported {
+        tiles,
+        width,
+        height,
+        spawns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::registry;
+
+    fn image(pixels: &[[u8; 4]], width: u16) -> Image {
+        Image {
+            bytes: pixels.concat(),
+            width,
+            height: pixels.len() as u16 / width,
+        }
+    }
+
+    #[test]
+    fn pixels_map_to_tiles_and_spawns() {
+        let registry = registry();
+        let palette = Palette::load("data/palette.toml", &registry).unwrap();
+        let black = [0, 0, 0, 255];
+        let white = [255, 255, 255, 255];
+        let green = [0, 255, 0, 255];
+
+        let level = from_image(
+            &image(&[black, black, black, white, green, white], 3),
+            &palette,
+            &registry,
+        )
+        .unwrap();
+
+        assert_eq!((level.width, level.height), (3, 2));
+        assert_eq!(level.tiles, vec![1, 1, 1, 0, 0, 0]);
+        assert_eq!(level.spawns[0].p, vec2(1.0, 1.0) * TILE_SIZE);
+    }
+
+    #[test]
+    fn unknown_colours_are_errors() {
+        let registry = registry();
+        let palette = Palette::parse("[colours]\n\"#000000\" = \"wall\"", &registry).unwrap();
+
+        let err = from_image(&image(&[[1, 2, 3, 255]], 1), &palette, &registry)
+            .err()
+            .expect("should fail");
+        assert!(err.to_string().contains("010203ff"));
+    }
+}
@@ -0,0 +1,760 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use hecs::Entity;
+use pathfinding::prelude::{astar, dijkstra_all};
+
+use crate::{
+    entities::WorldIndex,
+    events::{GameEvent, Listener},
+    steering,
+    tiles::Tiles,
+};
+
+// Borders open for at least this many cells
+// get an entrance at either end, shorter ones
+// a single one in the middle.
+const LONG_ENTRANCE: usize = 6;
+
+// Cheapest path over every cell, with its
+// cost. Slow on big levels, Hpa is what werfs
+// use.
+pub fn search(tiles: &Tiles, from: WorldIndex, goal: WorldIndex) -> Option<(Vec<WorldIndex>, u32)> {
+    astar(
+        &from,
+        |p| p.successors(tiles),
+        |p| p.distance(&goal, tiles),
+        |p| *p == goal,
+    )
+}
+
+// Hierarchical pathfinding. Each z-level is cut
+// into square clusters, and the cells where
+// two of them meet, or stairs lead out of
+// one, are entrances. Searches run over the
+// entrances, with the costs between those of
+// a cluster worked out beforehand, and only
+// the clusters on the way are searched cell
+// by cell. Paths can cost a little more than
+// the cheapest.
+//
+// Tile changes reach it as events, the
+// clusters around them are redone on update.
+#[derive(Debug)]
+pub struct Hpa {
+    // Cells per side of a cluster.
+    size: usize,
+    // Of the level, in cells.
+    width: usize,
+    height: usize,
+    // Clusters per z-level along x and y.
+    columns: usize,
+    rows: usize,
+    depth: usize,
+    // Pairs of cells facing each other across
+    // the right and bottom side of each
+    // cluster, the first one inside it.
+    borders: Vec<[Vec<(WorldIndex, WorldIndex)>; 2]>,
+    // Stairs and ramps, from a cell of the
+    // cluster to the one above or below.
+    stairs: Vec<Vec<(WorldIndex, WorldIndex, u32)>>,
+    // Out of every entrance of a cluster, to
+    // the others and across to neighbours.
+    edges: Vec<HashMap<WorldIndex, Vec<(WorldIndex, u32)>>>,
+    dirty: HashSet<usize>,
+}
+
+impl Hpa {
+    pub fn new(tiles: &Tiles, size: usize) -> Self {
+        let columns = tiles.width.div_ceil(size);
+        let rows = tiles.height.div_ceil(size);
+        let len = columns * rows * tiles.depth;
+
+        let mut hpa = Self {
+            size,
+            width: tiles.width,
+            height: tiles.height,
+            columns,
+            rows,
+            depth: tiles.depth,
+            borders: vec![[vec![], vec![]]; len],
+            stairs: vec![vec![]; len],
+            edges: vec![HashMap::new(); len],
+            dirty: HashSet::new(),
+        };
+        for cluster in 0..len {
+            hpa.find_entrances(tiles, cluster);
+        }
+        for cluster in 0..len {
+            hpa.connect(tiles, cluster);
+        }
+        hpa
+    }
+
+    // Redoes the clusters changed tiles were in,
+    // and the ones next to them.
+    pub fn update(&mut self, tiles: &Tiles) {
+        if self.dirty.is_empty() {
+            return;
+        }
+
+        let mut affected = HashSet::new();
+        for cluster in std::mem::take(&mut self.dirty) {
+            let around = self.around(cluster);
+            // Borders are kept by the cluster on
+            // their left or top, stairs by the one
+            // they start in.
+            for &other in &around {
+                let (cx, cy, cz) = self.xyz(cluster);
+                let (ox, oy, oz) = self.xyz(other);
+                let left_or_top = oz == cz && ox <= cx && oy <= cy;
+                if left_or_top || oz != cz {
+                    self.find_entrances(tiles, other);
+                }
+            }
+            affected.extend(around);
+        }
+        for cluster in affected {
+            self.connect(tiles, cluster);
+        }
+    }
+
+    // Path from one cell to another, pulled
+    // straight where the ground is open.
+    pub fn find(
+        &self,
+        tiles: &Tiles,
+        from: WorldIndex,
+        goal: WorldIndex,
+    ) -> Option<Vec<WorldIndex>> {
+        self.search(tiles, from, goal)
+            .map(|(path, _cost)| steering::smooth(&path, tiles))
+    }
+
+    // Every cell of the path, with its cost.
+    pub fn search(
+        &self,
+        tiles: &Tiles,
+        from: WorldIndex,
+        goal: WorldIndex,
+    ) -> Option<(Vec<WorldIndex>, u32)> {
+        let inside = |cell: WorldIndex| (0..tiles.len() as i32).contains(&cell.0);
+        if !inside(from) || !inside(goal) {
+            return None;
+        }
+
+        let start = self.cluster(from);
+        let end = self.cluster(goal);
+        // Within a cluster it's usually quicker
+        // to stay inside.
+        if start == end {
+            if let Some(found) = self.local(tiles, from, goal) {
+                return Some(found);
+            }
+        }
+
+        let reached = dijkstra_all(&from, |&cell| self.inside(tiles, cell, start));
+        let from_start = self
+            .entrances(start)
+            .into_iter()
+            .filter_map(|e| reached.get(&e).map(|&(_, cost)| (e, cost)))
+            .collect::<Vec<_>>();
+        let to_goal = self
+            .entrances(end)
+            .into_iter()
+            .filter_map(|e| self.local(tiles, e, goal).map(|(_, cost)| (e, cost)))
+            .collect::<HashMap<_, _>>();
+
+        let (waypoints, cost) = astar(
+            &from,
+            |&cell| {
+                let mut next = self.edges[self.cluster(cell)]
+                    .get(&cell)
+                    .cloned()
+                    .unwrap_or_default();
+                if cell == from {
+                    next.extend(&from_start);
+                }
+                if let Some(&cost) = to_goal.get(&cell) {
+                    next.push((goal, cost));
+                }
+                next
+            },
+            |cell| cell.distance(&goal, tiles),
+            |&cell| cell == goal,
+        )?;
+
+        // Entrances of the same cluster are
+        // joined cell by cell, the others are
+        // next to each other.
+        let mut path = vec![from];
+        for pair in waypoints.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if self.cluster(a) == self.cluster(b) {
+                // Fails if the graph is out of date.
+                let (cells, _) = self.local(tiles, a, b)?;
+                path.extend(&cells[1..]);
+            } else {
+                path.push(b);
+            }
+        }
+        Some((path, cost))
+    }
+
+    // Cheapest path without leaving the cluster.
+    fn local(
+        &self,
+        tiles: &Tiles,
+        from: WorldIndex,
+        goal: WorldIndex,
+    ) -> Option<(Vec<WorldIndex>, u32)> {
+        let cluster = self.cluster(from);
+        astar(
+            &from,
+            |&cell| self.inside(tiles, cell, cluster),
+            |cell| cell.distance(&goal, tiles),
+            |&cell| cell == goal,
+        )
+    }
+
+    fn inside(&self, tiles: &Tiles, cell: WorldIndex, cluster: usize) -> Vec<(WorldIndex, u32)> {
+        let mut next = cell.successors(tiles);
+        // Stairs lead out of it as well.
+        next.retain(|&(n, _)| self.cluster(n) == cluster);
+        next
+    }
+
+    fn find_entrances(&mut self, tiles: &Tiles, cluster: usize) {
+        let (cx, cy, z) = self.xyz(cluster);
+        let (x0, y0) = (cx * self.size, cy * self.size);
+        let x1 = (x0 + self.size).min(tiles.width);
+        let y1 = (y0 + self.size).min(tiles.height);
+        let cell = |x: usize, y: usize| tiles.index_at(x as i32, y as i32, z as i32);
+
+        // Right, then bottom.
+        let sides = [
+            (x1 < tiles.width).then(|| {
+                (y0..y1)
+                    .map(|y| (cell(x1 - 1, y), cell(x1, y)))
+                    .collect::<Vec<_>>()
+            }),
+            (y1 < tiles.height).then(|| {
+                (x0..x1)
+                    .map(|x| (cell(x, y1 - 1), cell(x, y1)))
+                    .collect::<Vec<_>>()
+            }),
+        ];
+        for (side, pairs) in sides.into_iter().enumerate() {
+            let pairs = pairs.unwrap_or_default();
+            let open = |(a, b): (Option<usize>, Option<usize>)| match (a, b) {
+                (Some(a), Some(b)) if !tiles.is_blocked(a) && !tiles.is_blocked(b) => {
+                    Some((WorldIndex(a as i32), WorldIndex(b as i32)))
+                }
+                _ => None,
+            };
+
+            let mut entrances = vec![];
+            let mut run = vec![];
+            for pair in pairs.into_iter().map(open).chain([None]) {
+                match pair {
+                    Some(pair) => run.push(pair),
+                    None if run.len() >= LONG_ENTRANCE => {
+                        entrances.push(run[0]);
+                        entrances.push(run[run.len() - 1]);
+                        run.clear();
+                    }
+                    None if !run.is_empty() => {
+                        entrances.push(run[run.len() / 2]);
+                        run.clear();
+                    }
+                    None => (),
+                }
+            }
+            self.borders[cluster][side] = entrances;
+        }
+
+        self.stairs[cluster] = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+            .filter_map(|(x, y)| cell(x, y))
+            .filter(|&i| {
+                let climb = tiles.climb(i);
+                climb.up() || climb.down()
+            })
+            .flat_map(|i| {
+                let from = WorldIndex(i as i32);
+                from.successors(tiles)
+                    .into_iter()
+                    .filter(move |(to, _)| to.z(tiles) != z)
+                    .map(move |(to, cost)| (from, to, cost))
+            })
+            .collect();
+    }
+
+    // Works out the edges out of the entrances
+    // of the cluster.
+    fn connect(&mut self, tiles: &Tiles, cluster: usize) {
+        let mut edges: HashMap<WorldIndex, Vec<(WorldIndex, u32)>> = HashMap::new();
+        for (from, to) in self.crossings(cluster) {
+            edges
+                .entry(from)
+                .or_default()
+                .push((to, tiles.cost(to.0 as usize)));
+        }
+        for &(from, to, cost) in &self.stairs[cluster] {
+            edges.entry(from).or_default().push((to, cost));
+        }
+
+        let entrances = self.entrances(cluster);
+        for &from in &entrances {
+            let reached = dijkstra_all(&from, |&cell| self.inside(tiles, cell, cluster));
+            let next = edges.entry(from).or_default();
+            next.extend(
+                entrances
+                    .iter()
+                    .filter_map(|e| reached.get(e).map(|&(_, cost)| (*e, cost))),
+            );
+        }
+        self.edges[cluster] = edges;
+    }
+
+    // Border pairs with one cell in the cluster,
+    // that one first.
+    fn crossings(&self, cluster: usize) -> Vec<(WorldIndex, WorldIndex)> {
+        let (cx, cy, z) = self.xyz(cluster);
+        let mut crossings = self.borders[cluster].concat();
+        if cx > 0 {
+            let left = self.index(cx - 1, cy, z);
+            crossings.extend(self.borders[left][0].iter().map(|&(a, b)| (b, a)));
+        }
+        if cy > 0 {
+            let top = self.index(cx, cy - 1, z);
+            crossings.extend(self.borders[top][1].iter().map(|&(a, b)| (b, a)));
+        }
+        crossings
+    }
+
+    // Cells of the cluster on the graph, where
+    // paths lead in or out.
+    fn entrances(&self, cluster: usize) -> Vec<WorldIndex> {
+        let (cx, cy, z) = self.xyz(cluster);
+        let mut entrances = self
+            .crossings(cluster)
+            .into_iter()
+            .map(|(inside, _)| inside)
+            .chain(self.stairs[cluster].iter().map(|&(from, _, _)| from))
+            .collect::<Vec<_>>();
+        for other in [z.checked_sub(1), Some(z + 1).filter(|&z| z < self.depth)]
+            .into_iter()
+            .flatten()
+        {
+            let other = self.index(cx, cy, other);
+            entrances.extend(
+                self.stairs[other]
+                    .iter()
+                    .map(|&(_, to, _)| to)
+                    .filter(|&to| self.cluster(to) == cluster),
+            );
+        }
+        entrances.sort_by_key(|e| e.0);
+        entrances.dedup();
+        entrances
+    }
+
+    // The cluster and those around it, on the
+    // same level and above and below.
+    fn around(&self, cluster: usize) -> Vec<usize> {
+        let (cx, cy, z) = self.xyz(cluster);
+        let mut around = vec![cluster];
+        if cx > 0 {
+            around.push(self.index(cx - 1, cy, z));
+        }
+        if cx + 1 < self.columns {
+            around.push(self.index(cx + 1, cy, z));
+        }
+        if cy > 0 {
+            around.push(self.index(cx, cy - 1, z));
+        }
+        if cy + 1 < self.rows {
+            around.push(self.index(cx, cy + 1, z));
+        }
+        if z > 0 {
+            around.push(self.index(cx, cy, z - 1));
+        }
+        if z + 1 < self.depth {
+            around.push(self.index(cx, cy, z + 1));
+        }
+        around
+    }
+
+    fn cluster(&self, cell: WorldIndex) -> usize {
+        let i = cell.0 as usize;
+        let (x, y) = (i % self.width, i / self.width % self.height);
+        let z = i / (self.width * self.height);
+        self.index(x / self.size, y / self.size, z)
+    }
+
+    fn index(&self, cx: usize, cy: usize, z: usize) -> usize {
+        cx + cy * self.columns + z * self.columns * self.rows
+    }
+
+    fn xyz(&self, cluster: usize) -> (usize, usize, usize) {
+        let level_len = self.columns * self.rows;
+        let i = cluster % level_len;
+        (i % self.columns, i / self.columns, cluster / level_len)
+    }
+}
+
+impl Listener for Hpa {
+    fn on_event(&mut self, event: &GameEvent) {
+        if let GameEvent::TileChanged(change) = event {
+            let cluster = self.cluster(WorldIndex(change.index as i32));
+            self.dirty.insert(cluster);
+        }
+    }
+}
+
+// One request of a werf. A newer one of the
+// same werf replaces it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathHandle {
+    pub werf: Entity,
+    id: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PathStatus {
+    Waiting,
+    Found(Vec<WorldIndex>),
+    // No path, or the request was replaced.
+    Failed,
+}
+
+// Systems ask for paths here instead of
+// searching themselves. Searches run once a
+// tick, for as long as the budget allows, so
+// a slow one can't stall a frame. Each werf
+// has at most one result waiting to be taken.
+#[derive(Debug, Default)]
+pub struct PathQueue {
+    next_id: u64,
+    pending: VecDeque<(PathHandle, WorldIndex, WorldIndex)>,
+    // The latest request of each werf.
+    latest: HashMap<Entity, u64>,
+    done: HashMap<Entity, (u64, Option<Vec<WorldIndex>>)>,
+}
+
+impl PathQueue {
+    pub fn submit(&mut self, werf: Entity, from: WorldIndex, goal: WorldIndex) -> PathHandle {
+        let handle = PathHandle {
+            werf,
+            id: self.next_id,
+        };
+        self.next_id += 1;
+        self.latest.insert(werf, handle.id);
+        self.done.remove(&werf);
+        self.pending.push_back((handle, from, goal));
+        handle
+    }
+
+    // Searches until the budget is used up, but
+    // at least once so every request gets its
+    // turn.
+    pub fn process(&mut self, hpa: &Hpa, tiles: &Tiles, budget: Duration) {
+        let start = Instant::now();
+        while let Some((handle, from, goal)) = self.pending.pop_front() {
+            if self.latest.get(&handle.werf) != Some(&handle.id) {
+                continue;
+            }
+            let path = hpa.find(tiles, from, goal);
+            self.done.insert(handle.werf, (handle.id, path));
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+    }
+
+    // Hands over the result once it's there.
+    pub fn take(&mut self, handle: PathHandle) -> PathStatus {
+        match self.done.get(&handle.werf) {
+            Some(&(id, _)) if id == handle.id => {
+                self.latest.remove(&handle.werf);
+                match self.done.remove(&handle.werf) {
+                    Some((_, Some(path))) => PathStatus::Found(path),
+                    _ => PathStatus::Failed,
+                }
+            }
+            _ if self.latest.get(&handle.werf) == Some(&handle.id) => PathStatus::Waiting,
+            _ => PathStatus::Failed,
+        }
+    }
+
+    // Forgets the werf's request and any path
+    // found for it.
+    pub fn cancel(&mut self, werf: Entity) {
+        self.latest.remove(&werf);
+        self.done.remove(&werf);
+    }
+
+    // Requests not searched yet.
+    pub fn waiting(&self) -> usize {
+        self.pending
+            .iter()
+            .filter(|(handle, _, _)| self.latest.get(&handle.werf) == Some(&handle.id))
+            .count()
+    }
+}
+
+// Despawned werfs won't come for their paths.
+impl Listener for PathQueue {
+    fn on_event(&mut self, event: &GameEvent) {
+        if let GameEvent::Despawned { werf } = event {
+            self.cancel(*werf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::{rngs::StdRng, Rng, SeedableRng};
+    use hecs::World;
+    use kd_tree::KdTree;
+    use macroquad::prelude::*;
+
+    use super::*;
+    use crate::{
+        constants::TILE_SIZE,
+        entities::{Position, State, Velocity},
+        events::Events,
+        steering::waypoint,
+        steps,
+        test_util::tiles,
+        tile::Tile,
+        tuning::Tuning,
+    };
+
+    const WALL: Tile = Tile(1);
+    const RAMP: u8 = 4;
+
+    // Tells the graph about the tiles changed
+    // since the last call.
+    fn build(tiles: &mut Tiles, hpa: &mut Hpa, walls: &[(i32, i32)]) {
+        for &(x, y) in walls {
+            let i = tiles.index_at(x, y, 0).unwrap();
+            tiles.set_tile(&mut StdRng::seed_from_u64(0), i, WALL);
+        }
+        for change in tiles.take_changed() {
+            hpa.on_event(&GameEvent::TileChanged(change));
+        }
+        hpa.update(tiles);
+    }
+
+    // Walks a werf from the left of a room to
+    // the right, building walls after a few
+    // ticks. It must never walk through them.
+    // Returns how it ended.
+    fn walk(walls: &[(i32, i32)]) -> GameEvent {
+        let mut tiles = tiles(vec![0; 18], 6, 3);
+        let mut hpa = Hpa::new(&tiles, 4);
+        let mut queue = PathQueue::default();
+        let mut events = Events::default();
+        let tuning = Tuning::default();
+        let from = WorldIndex::new(0, 1, 0, &tiles);
+        let goal = WorldIndex::new(5, 1, 0, &tiles);
+
+        let mut world = World::new();
+        let werf = world.spawn((
+            Position {
+                p: waypoint(from, &tiles),
+                z: 0,
+            },
+            Velocity { v: Vec2::ZERO },
+        ));
+        let handle = queue.submit(werf, from, goal);
+        world
+            .insert_one(werf, State::new_waiting(handle, goal))
+            .unwrap();
+
+        for tick in 0..600 {
+            if tick == 10 {
+                build(&mut tiles, &mut hpa, walls);
+            }
+            queue.process(&hpa, &tiles, Duration::ZERO);
+            let mut positions = vec![];
+            steps::movement(&mut world, &mut positions, &tuning);
+            let kdtree = KdTree::build_by_ordered_float(positions);
+            steps::state(
+                &mut world,
+                &tiles,
+                &mut queue,
+                &kdtree,
+                1.0 / 60.0,
+                &tuning,
+                &mut events,
+            );
+            events.flush(&mut []);
+            if let Some(event) = events.read().first() {
+                return event.clone();
+            }
+
+            let pos = *world.get::<&Position>(werf).unwrap();
+            let centre = (pos.p / TILE_SIZE + 0.25).floor();
+            let i = tiles.index_at(centre.x as i32, centre.y as i32, 0);
+            assert!(!tiles.is_blocked(i.unwrap()), "walked into a wall");
+        }
+        panic!("neither arrived nor gave up");
+    }
+
+    #[test]
+    fn werfs_walk_around_new_walls() {
+        let ended = walk(&[(3, 1), (3, 2)]);
+        assert!(matches!(ended, GameEvent::Arrived { .. }), "{:?}", ended);
+    }
+
+    #[test]
+    fn werfs_give_up_when_walled_off() {
+        let ended = walk(&[(3, 0), (3, 1), (3, 2)]);
+        assert!(
+            matches!(
+                ended,
+                GameEvent::PathFailed {
+                    goal: (5, 1, 0),
+                    ..
+                }
+            ),
+            "{:?}",
+            ended
+        );
+    }
+
+    #[test]
+    fn requests_are_replaced_and_let_go() {
+        let tiles = tiles(vec![0; 18], 6, 3);
+        let hpa = Hpa::new(&tiles, 4);
+        let mut queue = PathQueue::default();
+        let mut world = World::new();
+        let (a, b) = (world.spawn(()), world.spawn(()));
+        let (from, goal) = (WorldIndex(0), WorldIndex(17));
+
+        let old = queue.submit(a, from, goal);
+        let new = queue.submit(a, from, goal);
+        let other = queue.submit(b, from, goal);
+        assert_eq!(queue.take(old), PathStatus::Failed);
+        assert_eq!(queue.take(new), PathStatus::Waiting);
+        assert_eq!(queue.waiting(), 2);
+
+        // Without a budget one search a tick.
+        queue.process(&hpa, &tiles, Duration::ZERO);
+        assert_eq!(queue.waiting(), 1);
+        assert!(matches!(queue.take(new), PathStatus::Found(_)));
+        assert_eq!(queue.take(new), PathStatus::Failed);
+
+        queue.process(&hpa, &tiles, Duration::ZERO);
+        queue.on_event(&GameEvent::Despawned { werf: b });
+        assert_eq!(queue.take(other), PathStatus::Failed);
+    }
+
+    #[test]
+    fn relocated_werfs_drop_their_requests() {
+        let tiles = tiles(vec![0; 18], 6, 3);
+        let hpa = Hpa::new(&tiles, 4);
+        let mut queue = PathQueue::default();
+        let mut world = World::new();
+        let (from, goal) = (WorldIndex(0), WorldIndex(17));
+        let pos = Position {
+            p: waypoint(from, &tiles),
+            z: 0,
+        };
+        let (a, b) = (world.spawn((pos,)), world.spawn((pos,)));
+        let found = queue.submit(a, from, goal);
+        queue.process(&hpa, &tiles, Duration::ZERO);
+        let pending = queue.submit(b, from, goal);
+        world.insert_one(a, State::Idle).unwrap();
+        world
+            .insert_one(b, State::new_waiting(pending, goal))
+            .unwrap();
+
+        steps::relocate(&mut world, &tiles, &mut queue);
+        assert_eq!(queue.waiting(), 0);
+        assert_eq!(queue.take(found), PathStatus::Failed);
+        assert_eq!(queue.take(pending), PathStatus::Failed);
+        assert!(queue.latest.is_empty() && queue.done.is_empty());
+    }
+
+    #[test]
+    fn hierarchical_paths_are_close_to_the_cheapest() {
+        // Two levels of scattered walls, joined
+        // by a few ramps.
+        let mut rng = StdRng::seed_from_u64(1);
+        let (width, height) = (40, 40);
+        let mut grid = (0..width * height * 2)
+            .map(|_| if rng.gen_bool(0.3) { 1 } else { 0 })
+            .collect::<Vec<u8>>();
+        for _ in 0..6 {
+            let i = rng.gen_range(0..width * height);
+            grid[i] = RAMP;
+            grid[i + width * height] = RAMP;
+        }
+        let tiles = tiles(grid, width, height);
+        let hpa = Hpa::new(&tiles, 8);
+
+        let open = (0..tiles.len())
+            .filter(|&i| !tiles.is_blocked(i))
+            .map(|i| WorldIndex(i as i32))
+            .collect::<Vec<_>>();
+        let mut found = 0;
+        for _ in 0..200 {
+            let from = open[rng.gen_range(0..open.len())];
+            let goal = open[rng.gen_range(0..open.len())];
+            let flat = search(&tiles, from, goal);
+            let hierarchical = hpa.search(&tiles, from, goal);
+            let (Some((_, cheapest)), Some((path, cost))) = (&flat, hierarchical.clone()) else {
+                assert!(flat.is_none() && hierarchical.is_none());
+                continue;
+            };
+            found += 1;
+
+            let cheapest = *cheapest;
+            assert!(cost >= cheapest);
+            assert!(
+                cost as f32 <= cheapest as f32 * 1.5 + 4.0,
+                "{} vs {}",
+                cost,
+                cheapest
+            );
+
+            // Every step is one a werf can take.
+            assert_eq!((path[0], path[path.len() - 1]), (from, goal));
+            let walked = path
+                .windows(2)
+                .map(|pair| {
+                    let next = pair[0].successors(&tiles);
+                    let step = next.iter().find(|(cell, _)| *cell == pair[1]);
+                    step.expect("not a step").1
+                })
+                .sum::<u32>();
+            assert_eq!(walked, cost);
+        }
+        assert!(found > 50, "only {} found", found);
+    }
+
+    #[test]
+    fn tile_changes_update_the_graph() {
+        let mut tiles = tiles(vec![0; 8 * 4], 8, 4);
+        let mut hpa = Hpa::new(&tiles, 4);
+        let from = WorldIndex::new(0, 0, 0, &tiles);
+        let goal = WorldIndex::new(7, 3, 0, &tiles);
+        assert!(hpa.search(&tiles, from, goal).is_some());
+
+        build(&mut tiles, &mut hpa, &[(4, 0), (4, 1), (4, 2), (4, 3)]);
+        assert_eq!(hpa.search(&tiles, from, goal), None);
+
+        tiles.dig(&mut StdRng::seed_from_u64(0), 4 + 2 * 8);
+        build(&mut tiles, &mut hpa, &[]);
+        let (path, _) = hpa
+            .search(&tiles, from, goal)
+            .expect("no path after digging");
+        assert!(path.contains(&WorldIndex::new(4, 2, 0, &tiles)));
+    }
+}
@@ -0,0 +1,168 @@
+use macroquad::{color::hsl_to_rgb, prelude::*};
+
+use crate::{
+    assets::SheetDef,
+    layer::LayerKind,
+    tile::{TileDef, TileRegistry},
+};
+
+// Stand-ins for the art, which is not part of
+// the public repository. Good enough to tell
+// tile types and werfs apart.
+
+// One coloured figure per row, the second
+// column bobbing up for the walk animation.
+pub fn werfs(def: &SheetDef) -> Image {
+    let [w, h] = def.sprite;
+    let mut image = blank(def);
+
+    for row in 0..def.rows {
+        let colour = hsl_to_rgb(row as f32 / def.rows.max(1) as f32, 0.6, 0.55);
+        for column in 0..def.columns {
+            let x = column as u32 * w as u32;
+            let y = row as u32 * h as u32 + 1 - (column % 2) as u32;
+            let (w, h) = (w as u32, h as u32);
+            // Head and body.
+            fill_circle(&mut image, x + w / 2, y + h / 3, w / 5, colour);
+            fill_rect(&mut image, x + w / 4, y + h / 2, w / 2, h / 2, colour);
+        }
+    }
+
+    image
+}
+
+// Sprites used by a tile type are coloured by
+// it, and so are the rest of their row, which
+// covers autotile variants. Rows without any
+// tile type are grey.
+pub fn tileset(def: &SheetDef, registry: Option<&TileRegistry>) -> Image {
+    let [w, h] = def.sprite.map(u32::from);
+    let mut image = blank(def);
+
+    let defs = registry
+        .map(|r| r.iter().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let sheets = |tile: &TileDef| {
+        let mut sheets = vec![tile.sheet];
+        sheets.extend(tile.variants.iter().map(|v| v.sheet));
+        sheets
+    };
+
+    for row in 0..def.rows {
+        for column in 0..def.columns {
+            let tile = defs
+                .iter()
+                .find(|tile| sheets(tile).contains(&[column, row]))
+                .or_else(|| defs.iter().find(|tile| tile.sheet[1] == row));
+
+            let (x, y) = (column as u32 * w, row as u32 * h);
+            match tile {
+                Some(tile) => draw_tile(&mut image, tile, x, y, w, h),
+                None => fill_rect(&mut image, x, y, w, h, GRAY),
+            }
+        }
+    }
+
+    image
+}
+
+fn draw_tile(image: &mut Image, tile: &TileDef, x: u32, y: u32, w: u32, h: u32) {
+    if tile.open {
+        return;
+    }
+
+    let hue = (tile.id as f32 * 0.618).fract();
+    match tile.layer {
+        LayerKind::Floor => fill_rect(image, x, y, w, h, hsl_to_rgb(hue, 0.3, 0.25)),
+        LayerKind::Structure | LayerKind::Decoration if tile.climb.up() || tile.climb.down() => {
+            fill_rect(image, x, y, w, h, hsl_to_rgb(hue, 0.3, 0.25));
+            let colour = hsl_to_rgb(hue, 0.7, 0.6);
+            for i in 0..h / 2 {
+                // Points up for stairs up, down
+                // otherwise.
+                let row = if tile.climb.up() { i } else { h / 2 - 1 - i };
+                fill_rect(image, x + w / 2 - i, y + h / 4 + row, i * 2 + 1, 1, colour);
+            }
+        }
+        LayerKind::Structure | LayerKind::Decoration => {
+            fill_rect(image, x, y, w, h, hsl_to_rgb(hue, 0.2, 0.55));
+            outline(image, x, y, w, h, hsl_to_rgb(hue, 0.2, 0.35));
+        }
+    }
+}
+
+fn blank(def: &SheetDef) -> Image {
+    let [w, h] = def.sprite;
+    Image::gen_image_color(w * def.columns as u16, h * def.rows as u16, BLANK)
+}
+
+fn fill_rect(image: &mut Image, x: u32, y: u32, w: u32, h: u32, colour: Color) {
+    for py in y..(y + h).min(image.height as u32) {
+        for px in x..(x + w).min(image.width as u32) {
+            image.set_pixel(px, py, colour);
+        }
+    }
+}
+
+fn outline(image: &mut Image, x: u32, y: u32, w: u32, h: u32, colour: Color) {
+    fill_rect(image, x, y, w, 1, colour);
+    fill_rect(image, x, y + h - 1, w, 1, colour);
+    fill_rect(image, x, y, 1, h, colour);
+    fill_rect(image, x + w - 1, y, 1, h, colour);
+}
+
+fn fill_circle(image: &mut Image, cx: u32, cy: u32, r: u32, colour: Color) {
+    for py in cy.saturating_sub(r)..=cy + r {
+        for px in cx.saturating_sub(r)..=cx + r {
+            let (dx, dy) = (px as i32 - cx as i32, py as i32 - cy as i32);
+            if dx * dx + dy * dy <= (r * r) as i32 {
+                fill_rect(image, px, py, 1, 1, colour);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn sheet(columns: u8, rows: u8) -> SheetDef {
+        SheetDef {
+            name: "test".to_string(),
+            path: String::new(),
+            sprite: [16, 16],
+            columns,
+            rows,
+        }
+    }
+
+    #[test]
+    fn tile_types_get_their_own_colour() {
+        let image = tileset(&sheet(16, 8), Some(&test_util::registry()));
+
+        assert_eq!((image.width, image.height), (256, 128));
+        // Ground at [0, 0] and wall at [0, 1].
+        let ground = image.get_pixel(8, 8);
+        let wall = image.get_pixel(8, 24);
+        assert_ne!(ground, wall);
+        assert_ne!(ground.a, 0.0);
+        // The row without any tile type.
+        let grey: [u8; 4] = image.get_pixel(8, 40).into();
+        let expected: [u8; 4] = GRAY.into();
+        assert_eq!(grey, expected);
+    }
+
+    #[test]
+    fn every_werf_sprite_has_a_figure() {
+        let def = sheet(2, 4);
+        let image = werfs(&def);
+
+        for row in 0..4 {
+            for column in 0..2 {
+                let p = image.get_pixel(column * 16 + 8, row * 16 + 12);
+                assert_ne!(p.a, 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    fs::metadata,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// What to reload when a file changes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Watched {
+    Sheet(String),
+    // The level file, or anything it is
+    // built from.
+    Level,
+    Tuning,
+    Scripts,
+}
+
+// Polls modification times. Cheap enough for
+// a handful of files, and needs nothing
+// platform specific.
+#[derive(Default)]
+pub struct Watcher {
+    files: Vec<(PathBuf, Watched, Option<SystemTime>)>,
+}
+
+impl Watcher {
+    pub fn watch(&mut self, path: impl AsRef<Path>, watched: Watched) {
+        let path = path.as_ref().to_path_buf();
+        let modified = modified(&path);
+        self.files.push((path, watched, modified));
+    }
+
+    // Takes the current state as seen, for
+    // after writing a watched file ourselves.
+    pub fn refresh(&mut self) {
+        for (path, _, last) in &mut self.files {
+            *last = modified(path);
+        }
+    }
+
+    // Everything changed since the last call,
+    // without duplicates. Files that appear or
+    // disappear count as changed.
+    pub fn changed(&mut self) -> Vec<Watched> {
+        let mut changed = vec![];
+        for (path, watched, last) in &mut self.files {
+            let modified = modified(path);
+            if modified != *last {
+                *last = modified;
+                if !changed.contains(watched) {
+                    changed.push(watched.clone());
+                }
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        fs::{write, File},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn changes_are_reported_once() {
+        let path = env::temp_dir().join(format!("werfs_reload_{}", std::process::id()));
+        write(&path, "a").unwrap();
+
+        let mut watcher = Watcher::default();
+        watcher.watch(&path, Watched::Level);
+        assert!(watcher.changed().is_empty());
+
+        // Moved well ahead, some filesystems only
+        // keep modification times in whole
+        // seconds.
+        write(&path, "b").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(2);
+        File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(later))
+            .unwrap();
+        assert_eq!(watcher.changed(), vec![Watched::Level]);
+        assert!(watcher.changed().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.changed(), vec![Watched::Level]);
+    }
+}
@@ -0,0 +1,579 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use hecs::{Entity, World};
+use macroquad::prelude::*;
+use mlua::{Function, HookTriggers, Lua, LuaOptions, StdLib, Table, Value};
+
+use crate::{
+    constants::TILE_SIZE,
+    entities::{Position, State, Velocity},
+    events::{GameEvent, Listener},
+    tile::Tile,
+    tiles::Tiles,
+};
+
+// A single call is stopped after this many
+// instructions, so a stuck script can't hang
+// the game.
+const INSTRUCTION_LIMIT: u32 = 1_000_000;
+const HOOK_INTERVAL: u32 = 1000;
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+// Names of the game events, for game.on.
+pub const EVENTS: [&str; 7] = [
+    "arrived",
+    "path_failed",
+    "collided",
+    "tile_changed",
+    "dug",
+    "died",
+    "despawned",
+];
+
+fn event_name(event: &GameEvent) -> &'static str {
+    match event {
+        GameEvent::Arrived { .. } => EVENTS[0],
+        GameEvent::PathFailed { .. } => EVENTS[1],
+        GameEvent::Collided { .. } => EVENTS[2],
+        GameEvent::TileChanged(_) => EVENTS[3],
+        GameEvent::Dug { .. } => EVENTS[4],
+        GameEvent::Died { .. } => EVENTS[5],
+        GameEvent::Despawned { .. } => EVENTS[6],
+    }
+}
+
+// Changes scripts ask for, made by the
+// simulation once they have returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Spawn { amount: usize, at: (i32, i32, i32) },
+    Behave { werf: u32, behaviour: String },
+    Dig { at: (i32, i32, i32) },
+    Kill { werf: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Log(String),
+    Error(String),
+}
+
+// Lua with only the table, string and math
+// libraries, plus the game table:
+//   game.behaviour(name, fn(werf, dt))
+//   game.on(event, fn(event))
+//   game.tile(x, y, z), game.blocked(x, y, z)
+//   game.size()
+//   game.spawn(n, x, y, z), game.dig(x, y, z)
+//   game.behave(werf, name), game.kill(werf)
+//   game.log(text)
+//
+// Behaviours get a werf with id, x, y, z, vx
+// and vy, where vx and vy can be changed, and
+// return true once done.
+pub struct Scripts {
+    lua: Lua,
+    // Instructions run by the current call.
+    used: Rc<Cell<u32>>,
+    actions: Rc<RefCell<Vec<Action>>>,
+    messages: Rc<RefCell<Vec<Message>>>,
+    // Events with a handler, the rest are not
+    // worth keeping.
+    subscribed: Rc<RefCell<HashSet<String>>>,
+    events: Vec<GameEvent>,
+}
+
+impl Scripts {
+    // Every .lua file in the directory, in name
+    // order. Script errors are reported as
+    // messages, not returned.
+    pub fn load(dir: &Path, seed: u64, tiles: &Tiles) -> io::Result<Self> {
+        let scripts = Self::new(seed).map_err(io::Error::other)?;
+        for path in Self::paths(dir)? {
+            let source = fs::read_to_string(&path)?;
+            scripts.run(&path.display().to_string(), &source, tiles);
+        }
+        Ok(scripts)
+    }
+
+    // The scripts load runs, a missing directory
+    // has none.
+    pub fn paths(dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+                .collect::<Vec<_>>(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        paths.sort();
+        Ok(paths)
+    }
+
+    pub fn new(seed: u64) -> mlua::Result<Self> {
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+            LuaOptions::default(),
+        )?;
+        lua.set_memory_limit(MEMORY_LIMIT)?;
+        for name in ["dofile", "loadfile", "load"] {
+            lua.globals().set(name, Value::Nil)?;
+        }
+        // The same seed plays out the same.
+        seed_random(&lua, seed)?;
+
+        let used = Rc::new(Cell::new(0));
+        let budget = used.clone();
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_INTERVAL),
+            move |_, _| {
+                budget.set(budget.get() + HOOK_INTERVAL);
+                if budget.get() > INSTRUCTION_LIMIT {
+                    return Err(mlua::Error::runtime("script ran for too long"));
+                }
+                Ok(())
+            },
+        );
+
+        let scripts = Self {
+            lua,
+            used,
+            actions: Rc::default(),
+            messages: Rc::default(),
+            subscribed: Rc::default(),
+            events: vec![],
+        };
+        scripts.create_api()?;
+        Ok(scripts)
+    }
+
+    fn create_api(&self) -> mlua::Result<()> {
+        let lua = &self.lua;
+        let game = lua.create_table()?;
+        lua.set_named_registry_value("behaviours", lua.create_table()?)?;
+        lua.set_named_registry_value("handlers", lua.create_table()?)?;
+
+        game.set(
+            "behaviour",
+            lua.create_function(|lua, (name, f): (String, Function)| {
+                lua.named_registry_value::<Table>("behaviours")?
+                    .set(name, f)
+            })?,
+        )?;
+
+        let subscribed = self.subscribed.clone();
+        game.set(
+            "on",
+            lua.create_function(move |lua, (event, f): (String, Function)| {
+                if !EVENTS.contains(&event.as_str()) {
+                    return Err(mlua::Error::runtime(format!("unknown event {}", event)));
+                }
+                subscribed.borrow_mut().insert(event.clone());
+                let handlers: Table = lua.named_registry_value("handlers")?;
+                let list = match handlers.get::<_, Option<Table>>(event.as_str())? {
+                    Some(list) => list,
+                    None => {
+                        let list = lua.create_table()?;
+                        handlers.set(event, list.clone())?;
+                        list
+                    }
+                };
+                list.push(f)
+            })?,
+        )?;
+
+        let actions = self.actions.clone();
+        game.set(
+            "spawn",
+            lua.create_function(
+                move |_, (amount, x, y, z): (usize, i32, i32, Option<i32>)| {
+                    let at = (x, y, z.unwrap_or(0));
+                    actions.borrow_mut().push(Action::Spawn { amount, at });
+                    Ok(())
+                },
+            )?,
+        )?;
+
+        let actions = self.actions.clone();
+        game.set(
+            "dig",
+            lua.create_function(move |_, (x, y, z): (i32, i32, Option<i32>)| {
+                let at = (x, y, z.unwrap_or(0));
+                actions.borrow_mut().push(Action::Dig { at });
+                Ok(())
+            })?,
+        )?;
+
+        let actions = self.actions.clone();
+        game.set(
+            "behave",
+            lua.create_function(move |_, (werf, behaviour): (u32, String)| {
+                actions
+                    .borrow_mut()
+                    .push(Action::Behave { werf, behaviour });
+                Ok(())
+            })?,
+        )?;
+
+        let actions = self.actions.clone();
+        game.set(
+            "kill",
+            lua.create_function(move |_, werf: u32| {
+                actions.borrow_mut().push(Action::Kill { werf });
+                Ok(())
+            })?,
+        )?;
+
+        let messages = self.messages.clone();
+        game.set(
+            "log",
+            lua.create_function(move |_, text: String| {
+                messages.borrow_mut().push(Message::Log(text));
+                Ok(())
+            })?,
+        )?;
+
+        lua.globals().set("game", game)
+    }
+
+    // Runs a chunk of Lua, like a script file
+    // or a line typed in the console.
+    pub fn run(&self, name: &str, source: &str, tiles: &Tiles) {
+        let result = self.with_tiles(tiles, |lua| {
+            self.used.set(0);
+            lua.load(source).set_name(name).exec()
+        });
+        if let Err(err) = result {
+            self.error(err);
+        }
+    }
+
+    // Runs behaviours, then handlers for the
+    // events since the last update. Returns
+    // what they asked for.
+    pub fn update(&mut self, world: &mut World, tiles: &Tiles, dt: f32) -> Vec<Action> {
+        let events = std::mem::take(&mut self.events);
+
+        let result = self.with_tiles(tiles, |lua| {
+            let behaviours: Table = lua.named_registry_value("behaviours")?;
+            for (entity, (pos, vel, state)) in
+                world.query_mut::<(&Position, &mut Velocity, &mut State)>()
+            {
+                let State::Scripted(name) = state else {
+                    continue;
+                };
+                match self.behave(&behaviours, entity, pos, vel, name, dt) {
+                    Ok(false) => (),
+                    Ok(true) => *state = State::Idle,
+                    // One error per werf, not per tick.
+                    Err(err) => {
+                        self.error(err);
+                        *state = State::Idle;
+                    }
+                }
+            }
+
+            let handlers: Table = lua.named_registry_value("handlers")?;
+            for event in events {
+                if let Err(err) = self.handle(&handlers, tiles, event) {
+                    self.error(err);
+                }
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            self.error(err);
+        }
+
+        self.actions.take()
+    }
+
+    fn behave(
+        &self,
+        behaviours: &Table,
+        entity: Entity,
+        pos: &Position,
+        vel: &mut Velocity,
+        name: &str,
+        dt: f32,
+    ) -> mlua::Result<bool> {
+        let Some(f) = behaviours.get::<_, Option<Function>>(name)? else {
+            return Err(mlua::Error::runtime(format!("unknown behaviour {}", name)));
+        };
+
+        let werf = self.lua.create_table()?;
+        werf.set("id", entity.id())?;
+        werf.set("x", pos.p.x / TILE_SIZE)?;
+        werf.set("y", pos.p.y / TILE_SIZE)?;
+        werf.set("z", pos.z)?;
+        werf.set("vx", vel.v.x)?;
+        werf.set("vy", vel.v.y)?;
+
+        self.used.set(0);
+        let done = f.call::<_, Value>((werf.clone(), dt))?;
+        vel.v = vec2(werf.get("vx")?, werf.get("vy")?);
+        Ok(!matches!(done, Value::Nil | Value::Boolean(false)))
+    }
+
+    // Positions are in tiles, like everything
+    // else scripts see.
+    fn handle(&self, handlers: &Table, tiles: &Tiles, event: GameEvent) -> mlua::Result<()> {
+        let name = event_name(&event);
+        let tile_name = |tile: Option<Tile>| tile.map(|t| tiles.registry.get(t).name.clone());
+
+        let table = self.lua.create_table()?;
+        match event {
+            GameEvent::Arrived {
+                werf,
+                at: (x, y, z),
+            }
+            | GameEvent::PathFailed {
+                werf,
+                goal: (x, y, z),
+            } => {
+                table.set("werf", werf.id())?;
+                table.set("x", x)?;
+                table.set("y", y)?;
+                table.set("z", z)?;
+            }
+            GameEvent::Collided { werf, at } | GameEvent::Died { werf, at } => {
+                table.set("werf", werf.id())?;
+                table.set("x", at.p.x / TILE_SIZE)?;
+                table.set("y", at.p.y / TILE_SIZE)?;
+                table.set("z", at.z)?;
+            }
+            GameEvent::TileChanged(change) => {
+                let (x, y, z) = tiles.xyz(change.index);
+                table.set("x", x)?;
+                table.set("y", y)?;
+                table.set("z", z)?;
+                table.set("layer", format!("{:?}", change.kind).to_lowercase())?;
+                table.set("before", tile_name(change.before.tile))?;
+                table.set("after", tile_name(change.after.tile))?;
+            }
+            GameEvent::Dug {
+                at: (x, y, z),
+                tile,
+            } => {
+                table.set("x", x)?;
+                table.set("y", y)?;
+                table.set("z", z)?;
+                table.set("tile", tile_name(Some(tile)))?;
+            }
+            GameEvent::Despawned { werf } => {
+                table.set("werf", werf.id())?;
+            }
+        }
+
+        let Some(list) = handlers.get::<_, Option<Table>>(name)? else {
+            return Ok(());
+        };
+        for f in list.sequence_values::<Function>() {
+            self.used.set(0);
+            f?.call::<_, ()>(table.clone())?;
+        }
+        Ok(())
+    }
+
+    // Level queries only exist during a call,
+    // as they borrow the tiles.
+    fn with_tiles<R>(
+        &self,
+        tiles: &Tiles,
+        f: impl FnOnce(&Lua) -> mlua::Result<R>,
+    ) -> mlua::Result<R> {
+        self.lua.scope(|scope| {
+            let game: Table = self.lua.globals().get("game")?;
+            game.set(
+                "tile",
+                scope.create_function(|_, (x, y, z): (i32, i32, i32)| {
+                    Ok(tiles
+                        .index_at(x, y, z)
+                        .and_then(|i| tiles.top_tile(i))
+                        .map(|t| tiles.registry.get(t).name.clone()))
+                })?,
+            )?;
+            game.set(
+                "blocked",
+                scope.create_function(|_, (x, y, z): (i32, i32, i32)| {
+                    Ok(tiles.index_at(x, y, z).is_none_or(|i| tiles.is_blocked(i)))
+                })?,
+            )?;
+            game.set(
+                "size",
+                scope.create_function(|_, ()| Ok((tiles.width, tiles.height, tiles.depth)))?,
+            )?;
+            f(&self.lua)
+        })
+    }
+
+    // Tracebacks are left out, the first line
+    // has the file and line.
+    fn error(&self, err: mlua::Error) {
+        let err = err.to_string();
+        let line = err.lines().next().unwrap_or_default().to_string();
+        let mut messages = self.messages.borrow_mut();
+        // Many werfs failing the same way.
+        if messages.last() != Some(&Message::Error(line.clone())) {
+            messages.push(Message::Error(line));
+        }
+    }
+
+    pub fn report(&self, message: Message) {
+        self.messages.borrow_mut().push(message);
+    }
+
+    pub fn take_messages(&self) -> Vec<Message> {
+        self.messages.take()
+    }
+
+    // Restarts math.random, keeping everything
+    // else the scripts set up.
+    pub fn reseed(&self, seed: u64) {
+        if let Err(err) = seed_random(&self.lua, seed) {
+            self.error(err);
+        }
+    }
+}
+
+fn seed_random(lua: &Lua, seed: u64) -> mlua::Result<()> {
+    let math: Table = lua.globals().get("math")?;
+    math.get::<_, Function>("randomseed")?.call(seed as i64)
+}
+
+impl Listener for Scripts {
+    fn on_event(&mut self, event: &GameEvent) {
+        if self.subscribed.borrow().contains(event_name(event)) {
+            self.events.push(event.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn tiles() -> Tiles {
+        test_util::tiles(vec![1, 0, 0, 0], 2, 2)
+    }
+
+    #[test]
+    fn behaviours_steer_werfs() {
+        let tiles = tiles();
+        let scripts = Scripts::new(0).unwrap();
+        scripts.run(
+            "test",
+            r#"
+                game.behaviour("right", function(werf, dt)
+                    werf.vx = 1
+                    return werf.x > 1
+                end)
+            "#,
+            &tiles,
+        );
+
+        let mut scripts = scripts;
+        let mut world = World::new();
+        let werf = world.spawn((
+            Position {
+                p: vec2(0.0, 0.0),
+                z: 0,
+            },
+            Velocity { v: Vec2::ZERO },
+            State::Scripted("right".to_string()),
+        ));
+        scripts.update(&mut world, &tiles, 0.1);
+        assert_eq!(world.get::<&Velocity>(werf).unwrap().v, vec2(1.0, 0.0));
+
+        world.get::<&mut Position>(werf).unwrap().p.x = 2.0 * TILE_SIZE;
+        scripts.update(&mut world, &tiles, 0.1);
+        assert!(matches!(*world.get::<&State>(werf).unwrap(), State::Idle));
+    }
+
+    #[test]
+    fn events_reach_handlers() {
+        let tiles = tiles();
+        let mut scripts = Scripts::new(0).unwrap();
+        scripts.run(
+            "test",
+            r#"
+                game.on("dug", function(e)
+                    game.log(e.tile .. " at " .. e.x .. "," .. e.y)
+                    game.spawn(2, e.x, e.y)
+                    game.log(game.tile(0, 0, 0))
+                end)
+            "#,
+            &tiles,
+        );
+
+        scripts.on_event(&GameEvent::Dug {
+            at: (1, 0, 0),
+            tile: Tile(1),
+        });
+        // Nothing listens to these.
+        scripts.on_event(&GameEvent::Arrived {
+            werf: Entity::DANGLING,
+            at: (0, 0, 0),
+        });
+        assert_eq!(scripts.events.len(), 1);
+        let actions = scripts.update(&mut World::new(), &tiles, 0.1);
+        assert_eq!(
+            actions,
+            vec![Action::Spawn {
+                amount: 2,
+                at: (1, 0, 0)
+            }]
+        );
+        assert_eq!(
+            scripts.take_messages(),
+            vec![
+                Message::Log("wall at 1,0".to_string()),
+                Message::Log("wall".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn scripts_are_sandboxed() {
+        let tiles = tiles();
+        let scripts = Scripts::new(0).unwrap();
+        scripts.run("loop", "while true do end", &tiles);
+        scripts.run("io", "io.open('x')", &tiles);
+        scripts.run("file", "dofile('x')", &tiles);
+        scripts.run("event", "game.on('exploded', print)", &tiles);
+
+        let errors = scripts.take_messages();
+        assert_eq!(errors.len(), 4);
+        let Message::Error(err) = &errors[0] else {
+            panic!("expected an error");
+        };
+        assert!(err.contains("script ran for too long"), "{}", err);
+        let Message::Error(err) = &errors[3] else {
+            panic!("expected an error");
+        };
+        assert!(err.contains("unknown event exploded"), "{}", err);
+    }
+
+    #[test]
+    fn reseeding_repeats_random_numbers() {
+        let tiles = tiles();
+        let scripts = Scripts::new(3).unwrap();
+        let roll = || {
+            scripts.run("test", "game.log(math.random(1000000))", &tiles);
+            scripts.take_messages()
+        };
+
+        let first = roll();
+        assert_ne!(roll(), first);
+        scripts.reseed(3);
+        assert_eq!(roll(), first);
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use ::rand::{rngs::StdRng, SeedableRng};
+use hecs::{Entity, World};
+use kd_tree::KdTree;
+use macroquad::prelude::*;
+
+use crate::{
+    assets::Manifest,
+    cli::{LevelSource, Options},
+    constants::{
+        ANIMATION_INTERVAL, CLUSTER_SIZE, DEATH_TIME, HISTORY_CHANGES, PATH_BUDGET, TICK, TILE_SIZE,
+    },
+    entities::{Position, State, Velocity},
+    events::{Events, GameEvent},
+    history::History,
+    level::Level,
+    paths::{Hpa, PathQueue},
+    script::{Action, Message, Scripts},
+    spawn,
+    stats::Stats,
+    steps,
+    tile::Tile,
+    tuning::Tuning,
+};
+
+// In the order they run each tick.
+pub const SYSTEMS: [&str; 6] = [
+    "movement",
+    "collision",
+    "paths",
+    "state",
+    "scripts",
+    "animation",
+];
+
+// The level and its werfs, everything that
+// runs without a window.
+pub struct Simulation {
+    pub rng: StdRng,
+    pub level: Level,
+    pub world: World,
+    // The werf clicks send places. Another one
+    // takes over when it dies.
+    pub controlled: Option<Entity>,
+    pub spawn: Position,
+    pub tuning: Tuning,
+    pub paths: Hpa,
+    pub requests: PathQueue,
+    // Every tile change ends up in here, so
+    // only so many are kept around.
+    pub history: History,
+    pub scripts: Scripts,
+    pub events: Events,
+    pub stats: Stats,
+    pub seed: u64,
+    // Only stops the interactive loop, ticking
+    // by hand still works.
+    pub paused: bool,
+    pub ticks: usize,
+    // Since the last animation frame.
+    animation_time: f32,
+}
+
+impl Simulation {
+    pub fn new(options: &Options, manifest: &Manifest, seed: u64) -> io::Result<Self> {
+        let tuning = Tuning::load(&manifest.tuning).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to load {}: {}", manifest.tuning.display(), err),
+            )
+        })?;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let level = load_level(&mut rng, options, seed, manifest)?;
+
+        let mut world = World::new();
+        let spawn = level.spawns.first().copied().unwrap_or(Position {
+            p: vec2(level.tiles.width as f32, level.tiles.height as f32) * TILE_SIZE / 2.0,
+            z: 0,
+        });
+        let controlled =
+            spawn::many_werfs(&mut world, &mut rng, &level.tiles, spawn, options.werfs)
+                .ok_or_else(|| io::Error::other("no free cells to spawn werfs on"))?;
+
+        let scripts = Scripts::load(&manifest.scripts, seed, &level.tiles).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "failed to load scripts from {}: {}",
+                    manifest.scripts.display(),
+                    err
+                ),
+            )
+        })?;
+
+        let paths = Hpa::new(&level.tiles, CLUSTER_SIZE);
+
+        Ok(Self {
+            rng,
+            level,
+            world,
+            controlled: Some(controlled),
+            spawn,
+            tuning,
+            paths,
+            requests: PathQueue::default(),
+            history: History::new(HISTORY_CHANGES),
+            scripts,
+            events: Events::default(),
+            stats: Stats::default(),
+            seed,
+            paused: false,
+            ticks: 0,
+            animation_time: 0.0,
+        })
+    }
+
+    // At a fixed rate, for runs without a
+    // window.
+    pub fn tick(&mut self) -> [Duration; SYSTEMS.len()] {
+        self.update(TICK)
+    }
+
+    // Every system but drawing. Returns how long
+    // each of them took.
+    pub fn update(&mut self, dt: f32) -> [Duration; SYSTEMS.len()] {
+        let mut timings = [Duration::ZERO; SYSTEMS.len()];
+
+        let start = Instant::now();
+        let mut positions = Vec::with_capacity(self.world.len() as usize);
+        steps::movement(&mut self.world, &mut positions, &self.tuning);
+        timings[0] = start.elapsed();
+
+        let start = Instant::now();
+        // Steering uses it as well, to keep werfs
+        // apart.
+        let kdtree = KdTree::build_by_ordered_float(positions);
+        steps::collision(&mut self.world, &kdtree, &self.tuning, &mut self.events);
+        timings[1] = start.elapsed();
+
+        // Tiles changed since the last tick, by
+        // scripts, the console or the editor,
+        // are in the graph before any search.
+        self.events.extend(
+            self.level
+                .tiles
+                .take_changed()
+                .into_iter()
+                .map(GameEvent::TileChanged),
+        );
+        self.events.flush(&mut [
+            &mut self.stats,
+            &mut self.scripts,
+            &mut self.paths,
+            &mut self.requests,
+        ]);
+        self.paths.update(&self.level.tiles);
+
+        let start = Instant::now();
+        self.requests
+            .process(&self.paths, &self.level.tiles, PATH_BUDGET);
+        timings[2] = start.elapsed();
+
+        let start = Instant::now();
+        steps::state(
+            &mut self.world,
+            &self.level.tiles,
+            &mut self.requests,
+            &kdtree,
+            dt,
+            &self.tuning,
+            &mut self.events,
+        );
+        let mut expired = vec![];
+        steps::decay(&mut self.world, dt, &mut expired);
+        for werf in expired {
+            self.despawn(werf);
+        }
+        timings[3] = start.elapsed();
+
+        let start = Instant::now();
+        self.run_scripts(dt);
+        timings[4] = start.elapsed();
+
+        let start = Instant::now();
+        self.animation_time += dt;
+        if self.animation_time >= ANIMATION_INTERVAL {
+            self.animation_time -= ANIMATION_INTERVAL;
+            steps::animation(&mut self.world);
+        }
+        timings[5] = start.elapsed();
+
+        self.history.commit(&mut self.level.tiles);
+        self.ticks += 1;
+        timings
+    }
+
+    // Runs scripts, then whatever they asked
+    // for.
+    fn run_scripts(&mut self, dt: f32) {
+        let actions = self.scripts.update(&mut self.world, &self.level.tiles, dt);
+        for action in actions {
+            if let Err(err) = self.apply(action) {
+                self.scripts.report(Message::Error(err));
+            }
+        }
+    }
+
+    fn apply(&mut self, action: Action) -> Result<(), String> {
+        match action {
+            Action::Spawn {
+                amount,
+                at: (x, y, z),
+            } => {
+                let at = self.tile_position(x, y, z).ok_or_else(|| {
+                    format!("can't spawn outside of the level at {},{},{}", x, y, z)
+                })?;
+                spawn::many_werfs(
+                    &mut self.world,
+                    &mut self.rng,
+                    &self.level.tiles,
+                    at,
+                    amount,
+                );
+            }
+            Action::Behave { werf, behaviour } => {
+                let entity = self.find(werf).ok_or_else(|| format!("no werf {}", werf))?;
+                self.requests.cancel(entity);
+                self.world
+                    .insert_one(entity, State::Scripted(behaviour))
+                    .map_err(|_| format!("no werf {}", werf))?;
+            }
+            Action::Dig { at: (x, y, z) } => {
+                self.dig(x, y, z);
+            }
+            Action::Kill { werf } => {
+                let entity = self.find(werf).ok_or_else(|| format!("no werf {}", werf))?;
+                self.kill(entity);
+            }
+        }
+        Ok(())
+    }
+
+    // Werfs that aren't dead.
+    pub fn population(&self) -> usize {
+        self.world
+            .query::<&State>()
+            .iter()
+            .filter(|(_, state)| !state.is_dead())
+            .count()
+    }
+
+    // The werf lies down for a while before
+    // it is despawned. Returns false if it was
+    // dead already, or no werf at all.
+    pub fn kill(&mut self, werf: Entity) -> bool {
+        let Ok((pos, vel, state)) = self
+            .world
+            .query_one_mut::<(&Position, &mut Velocity, &mut State)>(werf)
+        else {
+            return false;
+        };
+        if state.is_dead() {
+            return false;
+        }
+        *state = State::Dead(DEATH_TIME);
+        vel.v = Vec2::ZERO;
+        self.events.send(GameEvent::Died { werf, at: *pos });
+
+        if self.controlled == Some(werf) {
+            self.controlled = self.first_alive();
+        }
+        true
+    }
+
+    // Removes the werf right away. Anything
+    // outside the world that refers to werfs
+    // lets go of it here, or on Despawned.
+    pub fn despawn(&mut self, werf: Entity) {
+        if self.world.despawn(werf).is_err() {
+            return;
+        }
+        self.events.send(GameEvent::Despawned { werf });
+        if self.controlled == Some(werf) {
+            self.controlled = self.first_alive();
+        }
+    }
+
+    fn first_alive(&self) -> Option<Entity> {
+        self.world
+            .query::<&State>()
+            .iter()
+            .find(|(_, state)| !state.is_dead())
+            .map(|(werf, _)| werf)
+    }
+
+    // Listeners hear about it during the next
+    // tick, before paths are searched.
+    pub fn dig(&mut self, x: i32, y: i32, z: i32) -> Option<Tile> {
+        let index = self.level.tiles.index_at(x, y, z)?;
+        let tile = self.level.tiles.dig(&mut self.rng, index)?;
+        self.events.send(GameEvent::Dug {
+            at: (x, y, z),
+            tile,
+        });
+        Some(tile)
+    }
+
+    // The corner of a tile, in world coordinates.
+    pub fn tile_position(&self, x: i32, y: i32, z: i32) -> Option<Position> {
+        self.level.tiles.index_at(x, y, z).map(|_| Position {
+            p: vec2(x as f32, y as f32) * TILE_SIZE,
+            z: z as usize,
+        })
+    }
+
+    pub fn find(&self, id: u32) -> Option<Entity> {
+        self.world
+            .query::<&Position>()
+            .iter()
+            .map(|(entity, _)| entity)
+            .find(|werf| werf.id() == id)
+    }
+}
+
+fn load_level(
+    rng: &mut StdRng,
+    options: &Options,
+    seed: u64,
+    manifest: &Manifest,
+) -> io::Result<Level> {
+    let path = match &options.level {
+        LevelSource::File(path) => path.clone(),
+        LevelSource::Default if manifest.level.exists() => {
+            manifest.level.to_string_lossy().into_owned()
+        }
+        LevelSource::Default | LevelSource::Cave => {
+            let (width, height) = options.cave_size;
+            return Level::generate(rng, seed, width, height, manifest).map_err(|err| {
+                io::Error::new(err.kind(), format!("failed to generate cave: {}", err))
+            });
+        }
+    };
+
+    Level::new(rng, &path, manifest)
+        .map_err(|err| io::Error::new(err.kind(), format!("failed to load {}: {}", path, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entities::WorldIndex, paths::PathStatus, test_util};
+
+    #[test]
+    fn tile_changes_are_bounded_without_the_editor() {
+        let mut sim = test_util::sim();
+        let (width, height) = (sim.level.tiles.width, sim.level.tiles.height);
+
+        // Repaints the whole level every tick,
+        // far more changes than are kept.
+        for n in 0..20 {
+            let tile = Tile(n % 2);
+            let tiles = &mut sim.level.tiles;
+            tiles.set_rect(&mut sim.rng, 1, 1, 0, width - 2, height - 2, tile);
+            sim.tick();
+        }
+
+        assert!(sim.history.len() <= HISTORY_CHANGES);
+        assert!(sim.level.tiles.take_changes().is_empty());
+    }
+
+    #[test]
+    fn paths_go_through_cells_dug_in_the_same_tick() {
+        let mut sim = test_util::sim();
+        let (width, height) = (sim.level.tiles.width, sim.level.tiles.height);
+        let tiles = &mut sim.level.tiles;
+        tiles.set_rect(&mut sim.rng, 0, 0, 0, width, height, Tile(1));
+        tiles.set_rect(&mut sim.rng, 10, 1, 0, 6, 1, Tile(0));
+        tiles.set_rect(&mut sim.rng, 17, 1, 0, 6, 1, Tile(0));
+        sim.tick();
+
+        // Walled off until the wall between,
+        // on the edge of a cluster, is dug out.
+        let werf = sim.world.spawn(());
+        let from = WorldIndex::new(10, 1, 0, &sim.level.tiles);
+        let goal = WorldIndex::new(22, 1, 0, &sim.level.tiles);
+        assert!(sim.dig(CLUSTER_SIZE as i32, 1, 0).is_some());
+        let handle = sim.requests.submit(werf, from, goal);
+        sim.tick();
+
+        let status = sim.requests.take(handle);
+        assert!(matches!(status, PathStatus::Found(_)), "{:?}", status);
+    }
+
+    #[test]
+    fn scripted_werfs_drop_their_requests() {
+        let mut sim = test_util::sim();
+        let werf = sim.controlled.unwrap();
+        let from = sim.spawn.to_world_index(&sim.level.tiles);
+        sim.requests.submit(werf, from, from);
+
+        let behave = |werf: u32| Action::Behave {
+            werf,
+            behaviour: "wander".to_string(),
+        };
+        sim.apply(behave(werf.id())).unwrap();
+        assert_eq!(sim.requests.waiting(), 0);
+        assert!(matches!(
+            *sim.world.get::<&State>(werf).unwrap(),
+            State::Scripted(_)
+        ));
+        assert_eq!(sim.apply(behave(99999)), Err("no werf 99999".to_string()));
+    }
+}
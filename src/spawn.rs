@@ -1,65 +1,103 @@
-use ::rand::{rngs::ThreadRng, Rng};
+use ::rand::{rngs::StdRng, Rng};
 use hecs::{Entity, World};
 use macroquad::prelude::*;
 
 use crate::{
     constants::TILE_SIZE,
     entities::{Animated, Position, State, Velocity},
+    tiles::Tiles,
 };
 
-pub fn two_werfs(counter: &mut i32, world: &mut World, rng: &mut ThreadRng) -> Entity {
-    let main = world.spawn(werf(
-        counter,
-        Vec2 {
-            x: screen_width() / 5.0 - TILE_SIZE * 8.0,
-            y: screen_height() / 5.0,
-        },
-        // Vec2 { x: 0.7, y: 0.0 },
-        Vec2 { x: 0.0, y: 0.0 },
-        rng.gen_range(0..=3),
-    ));
-    world.spawn(werf(
-        counter,
-        Vec2 {
-            x: screen_width() / 5.0 + TILE_SIZE * 8.0,
-            y: screen_height() / 5.0,
-        },
-        // Vec2 { x: -0.7, y: 0.0 },
-        Vec2 { x: 0.0, y: 0.0 },
-        rng.gen_range(0..=3),
-    ));
-    main
-}
+// Scattered over free cells around the center,
+// in a square that grows with the amount.
+// Returns the first werf.
+pub fn many_werfs(
+    world: &mut World,
+    rng: &mut StdRng,
+    tiles: &Tiles,
+    center: Position,
+    amount: usize,
+) -> Option<Entity> {
+    let radius = ((amount as f32).sqrt() as i32).max(8);
+    let (cx, cy, cz) = center.to_world_index(tiles).xyz(tiles);
 
-#[allow(dead_code)]
-pub fn many_werfs(counter: &mut i32, world: &mut World, amount: usize, rng: &mut ThreadRng) {
-    world.spawn_batch((0..amount).map(|_| {
-        werf(
-            counter,
-            Vec2 {
-                x: rng.gen_range(TILE_SIZE..screen_width() / 5.0 - TILE_SIZE * 2.0),
-                y: rng.gen_range(TILE_SIZE..screen_height() / 5.0 - TILE_SIZE * 2.0),
-            },
-            Vec2 {
-                x: rng.gen_range(-0.7..0.7),
-                y: rng.gen_range(-0.7..0.7),
+    // Found once, or the closest one to the
+    // center when the whole square is blocked.
+    let mut free = (cy - radius..=cy + radius)
+        .flat_map(|y| (cx - radius..=cx + radius).map(move |x| (x, y)))
+        .filter_map(|(x, y)| tiles.index_at(x, y, cz))
+        .filter(|&i| !tiles.is_blocked(i))
+        .collect::<Vec<_>>();
+    if free.is_empty() {
+        free.extend(
+            tiles
+                .index_at(cx, cy, cz)
+                .and_then(|i| tiles.nearest_unblocked(i)),
+        );
+    }
+    if free.is_empty() {
+        return None;
+    }
+
+    let mut first = None;
+    for _ in 0..amount {
+        let (x, y, z) = tiles.xyz(free[rng.gen_range(0..free.len())]);
+        let entity = world.spawn(werf(
+            Position {
+                // Anywhere within the cell, werfs
+                // are half a tile.
+                p: (vec2(x as f32, y as f32)
+                    + vec2(rng.gen_range(0.0..0.5), rng.gen_range(0.0..0.5)))
+                    * TILE_SIZE,
+                z: z as usize,
             },
+            Vec2::ZERO,
             rng.gen_range(0..=3),
-        )
-    }));
+        ));
+        first.get_or_insert(entity);
+    }
+    first
+}
+
+pub fn werf(p: Position, v: Vec2, sprite: u8) -> (Position, Velocity, Animated, State) {
+    (p, Velocity { v }, Animated { sprite, step: 0 }, State::Idle)
 }
 
-pub fn werf(
-    counter: &mut i32,
-    p: Vec2,
-    v: Vec2,
-    sprite: u8,
-) -> (Position, Velocity, Animated, State) {
-    *counter += 1;
-    (
-        Position { p },
-        Velocity { v },
-        Animated { sprite, step: 0 },
-        State::Idle,
-    )
+#[cfg(test)]
+mod tests {
+    use ::rand::SeedableRng;
+
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn werfs_stand_still_on_free_cells() {
+        #[rustfmt::skip]
+        let tiles = test_util::tiles(
+            vec![
+                1, 1, 1, 1,
+                1, 0, 1, 1,
+                1, 1, 1, 0,
+            ],
+            4,
+            3,
+        );
+        let mut world = World::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let center = Position {
+            p: Vec2::ZERO,
+            z: 0,
+        };
+
+        assert!(many_werfs(&mut world, &mut rng, &tiles, center, 100).is_some());
+        assert_eq!(world.len(), 100);
+        for (_, (pos, vel)) in world.query_mut::<(&Position, &Velocity)>() {
+            let index = pos.to_world_index(&tiles).0 as usize;
+            assert!(index == 5 || index == 11, "{}", index);
+            assert_eq!(vel.v, Vec2::ZERO);
+        }
+
+        let walls = test_util::tiles(vec![1; 4], 2, 2);
+        assert!(many_werfs(&mut world, &mut rng, &walls, center, 1).is_none());
+    }
 }
@@ -0,0 +1,26 @@
+use crate::events::{GameEvent, Listener};
+
+// Totals since the start, for the console.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub arrivals: u64,
+    pub collisions: u64,
+    pub tiles_changed: u64,
+    pub dug: u64,
+    pub deaths: u64,
+    pub paths_failed: u64,
+}
+
+impl Listener for Stats {
+    fn on_event(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::Arrived { .. } => self.arrivals += 1,
+            GameEvent::PathFailed { .. } => self.paths_failed += 1,
+            GameEvent::Collided { .. } => self.collisions += 1,
+            GameEvent::TileChanged(_) => self.tiles_changed += 1,
+            GameEvent::Dug { .. } => self.dug += 1,
+            GameEvent::Died { .. } => self.deaths += 1,
+            GameEvent::Despawned { .. } => (),
+        }
+    }
+}
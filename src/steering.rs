@@ -0,0 +1,224 @@
+use macroquad::prelude::*;
+
+use crate::{constants::TILE_SIZE, entities::WorldIndex, tiles::Tiles};
+
+// Desired velocities, in pixels per tick.
+// steer turns them into a change of velocity.
+
+// Full speed toward the target.
+pub fn seek(pos: Vec2, target: Vec2, max_speed: f32) -> Vec2 {
+    (target - pos).normalize_or_zero() * max_speed
+}
+
+// Like seek, but slows down inside radius so
+// the werf stops on the target instead of
+// running past it.
+pub fn arrive(pos: Vec2, target: Vec2, max_speed: f32, radius: f32) -> Vec2 {
+    let offset = target - pos;
+    let distance = offset.length();
+    if distance >= radius {
+        return seek(pos, target, max_speed);
+    }
+    offset.normalize_or_zero() * max_speed * distance / radius
+}
+
+// Away from neighbours closer than radius,
+// stronger the closer they are. Werfs on the
+// exact same spot are left to collision.
+pub fn separation(pos: Vec2, neighbours: &[Vec2], radius: f32) -> Vec2 {
+    neighbours
+        .iter()
+        .map(|&other| {
+            let away = pos - other;
+            let distance = away.length();
+            if distance == 0.0 || distance >= radius {
+                return Vec2::ZERO;
+            }
+            away / distance * (1.0 - distance / radius)
+        })
+        .sum()
+}
+
+// The change of velocity toward desired, no
+// larger than max_force.
+pub fn steer(desired: Vec2, vel: Vec2, max_force: f32) -> Vec2 {
+    (desired - vel).clamp_length_max(max_force)
+}
+
+// How far p is from the line between a and
+// b, or from its ends.
+pub fn drift(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let line = b - a;
+    let t = if line == Vec2::ZERO {
+        0.0
+    } else {
+        ((p - a).dot(line) / line.length_squared()).clamp(0.0, 1.0)
+    };
+    p.distance(a + line * t)
+}
+
+// Where a werf stands in a cell. Werfs are
+// half a tile, this centres them.
+pub fn waypoint(index: WorldIndex, tiles: &Tiles) -> Vec2 {
+    (index.to_vec(tiles) + 0.25) * TILE_SIZE
+}
+
+// String pulling. Drops the waypoints that
+// can be skipped by walking straight, so
+// werfs cross open ground in a line instead
+// of a staircase. Stairs are always kept.
+pub fn smooth(path: &[WorldIndex], tiles: &Tiles) -> Vec<WorldIndex> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    let mut smoothed = vec![path[0]];
+    let mut anchor = path[0];
+    for pair in path.windows(2).skip(1) {
+        let (prev, next) = (pair[0], pair[1]);
+        if !walkable(anchor, next, tiles) {
+            smoothed.push(prev);
+            anchor = prev;
+        }
+    }
+    smoothed.extend(path.last());
+    smoothed
+}
+
+// Whether a werf fits along the straight line
+// between two cells. Every cell it touches has
+// to be free, and no more expensive than the
+// ends, or the shortcut could cost more than
+// the path it replaces.
+pub fn walkable(from: WorldIndex, to: WorldIndex, tiles: &Tiles) -> bool {
+    let (ax, ay, az) = from.xyz(tiles);
+    let (bx, by, bz) = to.xyz(tiles);
+    if az != bz {
+        return false;
+    }
+
+    let cost = tiles.cost(from.0 as usize).max(tiles.cost(to.0 as usize));
+    let a = vec2(ax as f32, ay as f32) + 0.5;
+    let b = vec2(bx as f32, by as f32) + 0.5;
+
+    // Quarter tile steps, checking the corners
+    // of the werf at each.
+    let steps = ((b - a).length() * 4.0).ceil().max(1.0) as usize;
+    (0..=steps).all(|step| {
+        let centre = a.lerp(b, step as f32 / steps as f32);
+        [
+            vec2(-0.25, -0.25),
+            vec2(0.25, -0.25),
+            vec2(-0.25, 0.25),
+            vec2(0.25, 0.25),
+        ]
+        .into_iter()
+        .all(|corner| {
+            let p = centre + corner;
+            match tiles.index_at(p.x.floor() as i32, p.y.floor() as i32, az) {
+                Some(i) => !tiles.is_blocked(i) && tiles.cost(i) <= cost,
+                None => false,
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{Position, Progress, State, Velocity},
+        paths::PathQueue,
+        test_util::tiles,
+        tuning::Tuning,
+    };
+
+    fn path(cells: &[(i32, i32)], tiles: &Tiles) -> Vec<WorldIndex> {
+        cells
+            .iter()
+            .map(|&(x, y)| WorldIndex::new(x, y, 0, tiles))
+            .collect()
+    }
+
+    #[test]
+    fn standing_on_the_target_is_not_nan() {
+        let p = vec2(3.0, 4.0);
+        assert_eq!(seek(p, p, 2.0), Vec2::ZERO);
+        assert_eq!(arrive(p, p, 2.0, 10.0), Vec2::ZERO);
+        assert_eq!(separation(p, &[p], 10.0), Vec2::ZERO);
+        assert_eq!(arrive(p, vec2(3.0, 9.0), 2.0, 10.0), vec2(0.0, 1.0));
+        assert!(separation(p, &[vec2(5.0, 4.0)], 10.0).x < 0.0);
+    }
+
+    #[test]
+    fn paths_are_pulled_straight_around_walls() {
+        #[rustfmt::skip]
+        let tiles = tiles(
+            vec![
+                0, 0, 0, 0, 0,
+                0, 0, 0, 1, 0,
+                0, 0, 0, 1, 0,
+            ],
+            5,
+            3,
+        );
+
+        // Open ground, only the ends are left.
+        let open = path(&[(0, 0), (1, 0), (1, 1), (2, 1), (2, 2)], &tiles);
+        assert_eq!(smooth(&open, &tiles), path(&[(0, 0), (2, 2)], &tiles));
+
+        // The corner by the wall stays.
+        let around = path(
+            &[
+                (0, 2),
+                (1, 2),
+                (2, 2),
+                (2, 1),
+                (2, 0),
+                (3, 0),
+                (4, 0),
+                (4, 1),
+                (4, 2),
+            ],
+            &tiles,
+        );
+        assert_eq!(
+            smooth(&around, &tiles),
+            path(&[(0, 2), (2, 0), (4, 0), (4, 2)], &tiles)
+        );
+    }
+
+    #[test]
+    fn werfs_stop_at_the_end_of_their_path() {
+        let tiles = tiles(vec![0; 64], 8, 8);
+        let tuning = Tuning::default();
+        let goal = WorldIndex::new(6, 5, 0, &tiles);
+        let mut pos = Position {
+            p: waypoint(WorldIndex::new(1, 1, 0, &tiles), &tiles),
+            z: 0,
+        };
+        let mut vel = Velocity { v: Vec2::ZERO };
+        let mut queue = PathQueue::default();
+        let mut state = State::new_moving(vec![goal]);
+
+        // About a second and a half of walking.
+        let mut ticks = 0;
+        while state.update(
+            &tiles,
+            &mut queue,
+            &mut pos,
+            &mut vel,
+            &[],
+            1.0 / 60.0,
+            &tuning,
+        ) == Progress::Walking
+        {
+            pos.p += vel.v;
+            vel.v *= tuning.damping;
+            ticks += 1;
+            assert!(ticks < 300, "never arrived, at {}", pos.p);
+        }
+
+        assert!(pos.p.distance(waypoint(goal, &tiles)) < 1.0, "{}", pos.p);
+    }
+}
@@ -1,40 +1,66 @@
 use crate::{
-    constants::TILE_SIZE,
-    entities::{Animated, Position, State, Velocity},
+    assets::Sheet,
+    constants::{COLLISION_RADIUS, DEATH_TIME, SEPARATION_RADIUS, TILE_SIZE},
+    entities::{Animated, Position, Progress, State, Velocity},
+    events::{Events, GameEvent},
+    paths::PathQueue,
+    tiles::Tiles,
+    tuning::Tuning,
 };
 
-use hecs::World;
+use std::f32::consts::FRAC_PI_2;
+
+use hecs::{Entity, World};
+use kd_tree::KdTree;
 use macroquad::prelude::*;
 
-pub fn move_and_draw(world: &mut World, texture: &Texture2D, positions: &mut Vec<Position>) {
-    for (_id, (pos, vel, anim)) in world.query_mut::<(&mut Position, &mut Velocity, &Animated)>() {
+// Dead werfs stay where they fell, and are
+// left out of collisions.
+pub fn movement(world: &mut World, positions: &mut Vec<Position>, tuning: &Tuning) {
+    for (_id, (pos, vel, state)) in world.query_mut::<(&mut Position, &mut Velocity, &State)>() {
+        if state.is_dead() {
+            continue;
+        }
         pos.p.x += vel.v.x;
         pos.p.y += vel.v.y;
 
-        if vel.v.length_squared() < 0.001 {
+        if vel.v.length_squared() < tuning.stop_threshold {
             vel.v = vec2(0., 0.);
         } else {
-            vel.v *= 0.96;
+            vel.v *= tuning.damping;
+        }
+
+        positions.push(*pos);
+    }
+}
+
+// Only werfs on the visible z-level are drawn.
+// Dead ones lie down and fade out.
+pub fn draw(world: &mut World, sheet: &Sheet, z: usize) {
+    for (_id, (pos, vel, anim, state)) in
+        world.query_mut::<(&Position, &Velocity, &Animated, &State)>()
+    {
+        if pos.z != z {
+            continue;
         }
 
-        positions.push(Position { p: pos.p });
+        let (colour, rotation) = match state {
+            State::Dead(left) => (Color::new(1.0, 1.0, 1.0, left / DEATH_TIME), FRAC_PI_2),
+            _ => (WHITE, 0.0),
+        };
 
         draw_texture_ex(
-            &texture,
+            &sheet.texture,
             pos.p.x,
             pos.p.y,
-            WHITE,
+            colour,
             DrawTextureParams {
+                rotation,
                 dest_size: Some(Vec2 {
                     x: TILE_SIZE / 2.0,
                     y: TILE_SIZE / 2.0,
                 }),
-                source: Some(Rect {
-                    x: anim.step as f32 * TILE_SIZE,
-                    y: anim.sprite as f32 * TILE_SIZE,
-                    w: TILE_SIZE,
-                    h: TILE_SIZE,
-                }),
+                source: Some(sheet.source_at(anim.step, anim.sprite)),
                 flip_x: vel.v.x < 0.0,
                 ..Default::default()
             },
@@ -42,12 +68,23 @@ pub fn move_and_draw(world: &mut World, texture: &Texture2D, positions: &mut Vec
     }
 }
 
-pub fn collision(world: &mut World, positions: Vec<Position>) {
-    const RADIUS: f32 = TILE_SIZE / 4.0;
-    let kdtree = kd_tree::KdTree::build_by_ordered_float(positions);
-
-    for (_id, (pos, vel)) in world.query_mut::<(&mut Position, &mut Velocity)>() {
-        let nearest = kdtree.within_radius(pos, RADIUS * 2.0);
+pub fn collision(
+    world: &mut World,
+    kdtree: &KdTree<Position>,
+    tuning: &Tuning,
+    events: &mut Events,
+) {
+    for (id, (pos, vel, state)) in world.query_mut::<(&mut Position, &mut Velocity, &State)>() {
+        if state.is_dead() {
+            continue;
+        }
+        // The tree is flat, werfs on other
+        // z-levels have to be filtered out.
+        let nearest = kdtree
+            .within_radius(pos, COLLISION_RADIUS * 2.0)
+            .into_iter()
+            .filter(|other| other.z == pos.z)
+            .collect::<Vec<_>>();
         let collision = nearest.len() > 1;
 
         if collision {
@@ -58,23 +95,14 @@ pub fn collision(world: &mut World, positions: Vec<Position>) {
             // Scales the distance to move the werf.
             // The idea is to have them collide but
             // then slide through each other.
-            // This will have to be tuned.
-            let setback = RADIUS * 1.8;
-            // let setback = RADIUS * 1.74;
+            let setback = COLLISION_RADIUS * tuning.setback;
 
-            pos.p.x = mid_x + RADIUS * (pos.p.x - other.p.x) / setback;
-            pos.p.y = mid_y + RADIUS * (pos.p.y - other.p.y) / setback;
+            pos.p.x = mid_x + COLLISION_RADIUS * (pos.p.x - other.p.x) / setback;
+            pos.p.y = mid_y + COLLISION_RADIUS * (pos.p.y - other.p.y) / setback;
 
-            vel.v *= 0.85;
+            vel.v *= tuning.collision_damping;
+            events.send(GameEvent::Collided { werf: id, at: *pos });
         }
-
-        draw_circle_lines(
-            pos.p.x + RADIUS,
-            pos.p.y + RADIUS,
-            RADIUS,
-            1.4,
-            if collision { RED } else { GREEN },
-        );
     }
 }
 
@@ -88,9 +116,59 @@ pub fn animation(world: &mut World) {
     }
 }
 
-pub fn state(world: &mut World, world_width: usize, dt: f32) {
-    for (_id, (pos, vel, state)) in world.query_mut::<(&Position, &mut Velocity, &mut State)>() {
-        state.update(world_width, pos, vel, dt);
+// Werfs reaching the end of their path send
+// Arrived, ones giving up on it PathFailed.
+// Lost ones ask for a new path.
+pub fn state(
+    world: &mut World,
+    tiles: &Tiles,
+    paths: &mut PathQueue,
+    kdtree: &KdTree<Position>,
+    dt: f32,
+    tuning: &Tuning,
+    events: &mut Events,
+) {
+    let mut neighbours = vec![];
+    for (id, (pos, vel, state)) in world.query_mut::<(&mut Position, &mut Velocity, &mut State)>() {
+        // Only moving werfs steer.
+        neighbours.clear();
+        if let State::Moving(_) = state {
+            neighbours.extend(
+                kdtree
+                    .within_radius(pos, SEPARATION_RADIUS)
+                    .into_iter()
+                    .filter(|other| other.z == pos.z)
+                    .map(|other| other.p),
+            );
+        }
+        match state.update(tiles, paths, pos, vel, &neighbours, dt, tuning) {
+            Progress::Walking => (),
+            Progress::Arrived => {
+                let at = pos.to_world_index(tiles).xyz(tiles);
+                events.send(GameEvent::Arrived { werf: id, at });
+            }
+            Progress::Lost(goal) => {
+                let handle = paths.submit(id, pos.to_world_index(tiles), goal);
+                state.repath(handle, goal);
+            }
+            Progress::Failed(goal) => {
+                let goal = goal.xyz(tiles);
+                events.send(GameEvent::PathFailed { werf: id, goal });
+            }
+        }
+    }
+}
+
+// Counts down dead werfs, the ones whose time
+// is up are added to expired.
+pub fn decay(world: &mut World, dt: f32, expired: &mut Vec<Entity>) {
+    for (id, state) in world.query_mut::<&mut State>() {
+        if let State::Dead(left) = state {
+            *left -= dt;
+            if *left <= 0.0 {
+                expired.push(id);
+            }
+        }
     }
 }
 
@@ -100,3 +178,33 @@ pub fn position_for(world: &mut hecs::World, entity: hecs::Entity) -> Option<Pos
         _ => None,
     }
 }
+
+// After the level changed under the werfs.
+// Paths may lead through new walls, so every
+// werf stops, and werfs now inside a wall or
+// outside the level move to the closest free
+// cell. Their requests are dropped.
+pub fn relocate(world: &mut World, tiles: &Tiles, paths: &mut PathQueue) {
+    let max = vec2(tiles.width as f32, tiles.height as f32) * TILE_SIZE - 1.0;
+
+    for (id, (pos, state)) in world.query_mut::<(&mut Position, &mut State)>() {
+        if !state.is_dead() {
+            *state = State::Idle;
+        }
+        paths.cancel(id);
+
+        pos.p = pos.p.clamp(Vec2::ZERO, max);
+        pos.z = pos.z.min(tiles.depth - 1);
+
+        let index = pos.to_world_index(tiles).0 as usize;
+        if !tiles.is_blocked(index) {
+            continue;
+        }
+        if let Some(free) = tiles.nearest_unblocked(index) {
+            let (x, y, z) = tiles.xyz(free);
+            // Centered, werfs are half a tile.
+            pos.p = (vec2(x as f32, y as f32) + 0.25) * TILE_SIZE;
+            pos.z = z as usize;
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use ::rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    assets::Manifest,
+    autotile::Autotile,
+    cli::{LevelSource, Options},
+    sim::Simulation,
+    tile::TileRegistry,
+    tiles::Tiles,
+};
+
+// Fixtures the tests share.
+
+pub fn registry() -> TileRegistry {
+    TileRegistry::load("data/tiles.toml", 16, 16).expect("failed to load tiles")
+}
+
+// Ids as in data/tiles.toml, one row after
+// another.
+pub fn tiles(grid: Vec<u8>, width: usize, height: usize) -> Tiles {
+    tiles_with(grid, width, height, Autotile::new(16, 16).unwrap())
+}
+
+pub fn tiles_with(grid: Vec<u8>, width: usize, height: usize, autotile: Autotile) -> Tiles {
+    Tiles::new(
+        &mut StdRng::seed_from_u64(0),
+        grid,
+        width,
+        height,
+        registry(),
+        autotile,
+    )
+    .expect("invalid grid")
+}
+
+// A generated cave with seed 1, and the
+// default number of werfs.
+pub fn sim() -> Simulation {
+    let manifest = Manifest::load(Some(PathBuf::from("."))).unwrap();
+    let options = Options {
+        level: LevelSource::Cave,
+        ..Options::default()
+    };
+    Simulation::new(&options, &manifest, 1).unwrap()
+}
@@ -1,45 +1,327 @@
-#[allow(dead_code)]
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum Tile {
-    Ground01 = 0,
-    Ground02 = 1,
-    Ground03 = 2,
-    WallTop01 = 16,
-    WallTop02 = 17,
-    WallTop03 = 18,
-    WallTop04 = 19,
-    WallTop05 = 20,
-    WallSide01 = 32,
-    WallSide02 = 33,
-    WallSide03 = 34,
-    WallSide04 = 35,
-    WallSide05 = 36,
-    Red = 96,
-}
-
-impl Tile {
-    pub fn is_wall(self) -> bool {
-        matches!(
-            self,
-            Tile::WallTop01
-                | Tile::WallTop02
-                | Tile::WallTop03
-                | Tile::WallTop04
-                | Tile::WallTop05
-                | Tile::WallSide01
-                | Tile::WallSide02
-                | Tile::WallSide03
-                | Tile::WallSide04
-                | Tile::WallSide05
+use std::{fs::read_to_string, io, path::Path};
+
+use ::rand::{rngs::StdRng, Rng};
+use serde::Deserialize;
+
+use crate::layer::LayerKind;
+
+// Id of a tile type in the registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tile(pub u8);
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Variant {
+    pub sheet: [u8; 2],
+    pub weight: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TileDef {
+    pub id: u8,
+    pub name: String,
+    pub sheet: [u8; 2],
+    #[serde(default)]
+    pub variants: Vec<Variant>,
+    #[serde(default)]
+    pub blocked: bool,
+    #[serde(default = "default_cost")]
+    pub cost: u32,
+    #[serde(default)]
+    pub diggable: bool,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub autotile: bool,
+    #[serde(default)]
+    pub layer: LayerKind,
+    #[serde(default)]
+    pub climb: Climb,
+    // Leaves the cell empty on every layer,
+    // so the level below shows through.
+    #[serde(default)]
+    pub open: bool,
+    // Drawn by the terminal renderer.
+    #[serde(default)]
+    pub glyph: Option<char>,
+}
+
+// Which z-levels can be reached from a tile.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Climb {
+    #[default]
+    None,
+    Up,
+    Down,
+    Both,
+}
+
+impl Climb {
+    pub fn up(self) -> bool {
+        matches!(self, Climb::Up | Climb::Both)
+    }
+
+    pub fn down(self) -> bool {
+        matches!(self, Climb::Down | Climb::Both)
+    }
+}
+
+fn default_cost() -> u32 {
+    1
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TileFile {
+    tile: Vec<TileDef>,
+}
+
+#[derive(Debug)]
+pub struct TileRegistry {
+    // Indexed by tile id.
+    defs: Vec<Option<TileDef>>,
+    // Of the tilesheet, to turn sheet
+    // positions into sprite indices.
+    columns: u8,
+}
+
+impl TileRegistry {
+    pub fn load(path: impl AsRef<Path>, columns: u8, rows: u8) -> io::Result<Self> {
+        Self::parse(&read_to_string(path)?, columns, rows)
+    }
+
+    // Columns and rows of the tilesheet, every
+    // sprite has to be on it.
+    pub fn parse(s: &str, columns: u8, rows: u8) -> io::Result<Self> {
+        let file: TileFile =
+            toml::from_str(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let mut defs: Vec<Option<TileDef>> = (0..=u8::MAX).map(|_| None).collect();
+        for def in file.tile {
+            let id = def.id as usize;
+            if defs[id].is_some() {
+                return Err(invalid(format!("duplicate tile id {}", id)));
+            }
+            if defs.iter().flatten().any(|other| other.name == def.name) {
+                return Err(invalid(format!("duplicate tile name {}", def.name)));
+            }
+            // Searches take every step to cost at
+            // least 1.
+            if def.cost < 1 {
+                return Err(invalid(format!(
+                    "tile {} has cost {}, it has to be at least 1",
+                    def.name, def.cost
+                )));
+            }
+            if !(0.0..=1.0).contains(&def.opacity) {
+                return Err(invalid(format!(
+                    "tile {} has opacity {}, outside of 0 to 1",
+                    def.name, def.opacity
+                )));
+            }
+            let sheets = def.variants.iter().map(|v| v.sheet);
+            for [x, y] in [def.sheet].into_iter().chain(sheets) {
+                // Sprite indices are u8 as well.
+                if x >= columns || y >= rows || sheet_index([x, y], columns) > u8::MAX as usize {
+                    return Err(invalid(format!(
+                        "tile {} uses sheet position [{}, {}], outside of the {}x{} sheet",
+                        def.name, x, y, columns, rows
+                    )));
+                }
+            }
+            defs[id] = Some(def);
+        }
+
+        Ok(Self { defs, columns })
+    }
+
+    pub fn contains(&self, id: u8) -> bool {
+        self.defs[id as usize].is_some()
+    }
+
+    // Tiles are only ever created from ids known
+    // to the registry, hence panic.
+    pub fn get(&self, tile: Tile) -> &TileDef {
+        self.defs[tile.0 as usize]
+            .as_ref()
+            .unwrap_or_else(|| panic!("unknown tile id {}", tile.0))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TileDef> {
+        self.defs.iter().flatten()
+    }
+
+    pub fn open_tile(&self) -> Option<Tile> {
+        self.iter().find(|def| def.open).map(|def| Tile(def.id))
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Tile> {
+        self.defs
+            .iter()
+            .flatten()
+            .find(|def| def.name == name)
+            .map(|def| Tile(def.id))
+    }
+
+    pub fn is_blocked(&self, tile: Tile) -> bool {
+        self.get(tile).blocked
+    }
+
+    pub fn sprite(&self, tile: Tile) -> u8 {
+        self.sheet_index(self.get(tile).sheet)
+    }
+
+    pub fn random_sprite(&self, rng: &mut StdRng, tile: Tile) -> u8 {
+        let def = self.get(tile);
+        let total = def.variants.iter().map(|v| v.weight).sum::<u32>();
+        if total == 0 {
+            return self.sheet_index(def.sheet);
+        }
+
+        let mut roll = rng.gen_range(0..total);
+        for variant in &def.variants {
+            if roll < variant.weight {
+                return self.sheet_index(variant.sheet);
+            }
+            roll -= variant.weight;
+        }
+
+        self.sheet_index(def.sheet)
+    }
+
+    // Positions were checked when loading.
+    fn sheet_index(&self, sheet: [u8; 2]) -> u8 {
+        sheet_index(sheet, self.columns) as u8
+    }
+}
+
+fn sheet_index([x, y]: [u8; 2], columns: u8) -> usize {
+    x as usize + y as usize * columns as usize
+}
+
+fn invalid(message: impl AsRef<str>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::SeedableRng;
+
+    use super::*;
+
+    fn parse(s: &str) -> io::Result<TileRegistry> {
+        TileRegistry::parse(s, 4, 2)
+    }
+
+    fn error(s: &str) -> String {
+        parse(s).expect_err("should fail").to_string()
+    }
+
+    #[test]
+    fn tiles_are_parsed_with_defaults() {
+        let registry = parse(
+            r#"
+            [[tile]]
+            id = 0
+            name = "ground"
+            sheet = [1, 1]
+
+            [[tile]]
+            id = 7
+            name = "stairs"
+            sheet = [3, 0]
+            cost = 2
+            layer = "structure"
+            climb = "up"
+            "#,
         )
+        .unwrap();
+
+        assert!(registry.contains(7) && !registry.contains(1));
+        assert_eq!(registry.by_name("stairs"), Some(Tile(7)));
+        assert_eq!(registry.sprite(Tile(0)), 5);
+
+        let ground = registry.get(Tile(0));
+        assert_eq!((ground.cost, ground.blocked), (1, false));
+        assert_eq!(ground.opacity, 1.0);
+        assert_eq!(ground.layer, LayerKind::Floor);
+        let stairs = registry.get(Tile(7));
+        assert_eq!(stairs.layer, LayerKind::Structure);
+        assert!(stairs.climb.up() && !stairs.climb.down());
+    }
+
+    #[test]
+    fn bad_tiles_are_rejected() {
+        let twice = |a: &str, b: &str| {
+            format!(
+                "[[tile]]\n{}\nsheet = [0, 0]\n[[tile]]\n{}\nsheet = [0, 0]",
+                a, b
+            )
+        };
+        assert!(
+            error(&twice("id = 1\nname = \"a\"", "id = 1\nname = \"b\""))
+                .contains("duplicate tile id 1")
+        );
+        assert!(
+            error(&twice("id = 1\nname = \"a\"", "id = 2\nname = \"a\""))
+                .contains("duplicate tile name a")
+        );
+
+        let one = |line: &str| format!("[[tile]]\nid = 0\nname = \"a\"\n{}", line);
+        assert!(error(&one("sheet = [0, 0]\nsolid = true")).contains("solid"));
+        assert!(error(&one("sheet = [0, 0]\nlayer = \"roof\"")).contains("roof"));
+        assert!(error(&one("sheet = [0, 0]\ncost = 0")).contains("cost 0"));
+        assert!(error(&one("sheet = [0, 0]\nopacity = 1.5")).contains("opacity 1.5"));
+        assert!(error(&one("sheet = [0, 0]\nopacity = -0.1")).contains("opacity -0.1"));
+        assert!(error(&one("sheet = [4, 0]")).contains("[4, 0]"));
+        assert!(error(&one("sheet = [0, 2]")).contains("[0, 2]"));
+        assert!(error(&one(
+            "sheet = [0, 0]\nvariants = [{ sheet = [0, 9], weight = 1 }]"
+        ))
+        .contains("[0, 9]"));
     }
 
-    pub fn is_ground(self) -> bool {
-        matches!(self, Tile::Ground01 | Tile::Ground02 | Tile::Ground03)
+    #[test]
+    fn sprite_indices_fit_the_sheet() {
+        let s = "[[tile]]\nid = 0\nname = \"a\"\nsheet = [0, 16]";
+        let err = TileRegistry::parse(s, 16, 17).expect_err("should fail");
+        assert!(err.to_string().contains("[0, 16]"));
+
+        let s = "[[tile]]\nid = 0\nname = \"a\"\nsheet = [15, 15]";
+        let registry = TileRegistry::parse(s, 16, 16).unwrap();
+        assert_eq!(registry.sprite(Tile(0)), 255);
     }
 
-    pub fn is_blocked(self) -> bool {
-        self.is_wall()
+    #[test]
+    fn variants_are_picked_by_weight() {
+        let registry = parse(
+            r#"
+            [[tile]]
+            id = 0
+            name = "ground"
+            sheet = [0, 0]
+            variants = [
+                { sheet = [1, 0], weight = 3 },
+                { sheet = [2, 0], weight = 1 },
+                { sheet = [3, 0], weight = 0 },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut counts = [0; 4];
+        for _ in 0..4000 {
+            counts[registry.random_sprite(&mut rng, Tile(0)) as usize] += 1;
+        }
+        assert_eq!((counts[0], counts[3]), (0, 0));
+        assert!((2800..3200).contains(&counts[1]), "{:?}", counts);
+        assert!((800..1200).contains(&counts[2]), "{:?}", counts);
     }
 }
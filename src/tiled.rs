@@ -0,0 +1,727 @@
+use std::{collections::HashMap, fs::read_to_string, io, path::Path};
+
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    constants::TILE_SIZE,
+    entities::Position,
+    layer::{Layer, LayerKind},
+    tile::{Tile, TileRegistry},
+};
+
+// Tiled stores flipping and rotation in the
+// top bits of every gid.
+const GID_FLAGS: u32 = 0xf000_0000;
+
+// Every z-level below the highest one used
+// is allocated, a typo shouldn't take all
+// the memory there is.
+const MAX_DEPTH: usize = 64;
+// Same for the cells across all of them,
+// a 2048x2048 map with 4 levels.
+const MAX_CELLS: usize = 1 << 24;
+
+// Rectangular area of a level, named in Tiled.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub name: String,
+    pub z: usize,
+    // In world coordinates.
+    pub rect: Rect,
+}
+
+pub struct Imported {
+    pub layers: Vec<Layer>,
+    pub width: usize,
+    pub height: usize,
+    pub spawns: Vec<Position>,
+    pub zones: Vec<Zone>,
+}
+
+// Supports orthogonal, finite maps with CSV
+// encoded (or plain XML) tile layers and
+// embedded tilesets.
+//
+// Tile layers are named after the layer they
+// end up in (floor, structure, decoration)
+// and take their z-level from an integer
+// property called z.
+//
+// Tileset tiles map to a registry tile by their
+// class, or a string property called tile.
+// Tiles without either fall back to using their
+// id within the tileset as registry id.
+//
+// Objects with class spawn become werf spawn
+// points, objects with class zone become zones.
+pub fn load(path: &str, registry: &TileRegistry) -> io::Result<Imported> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+
+    let s = read_to_string(path)?;
+    let map = match extension {
+        "tmx" => parse_tmx(&s)?,
+        _ => parse_tmj(&s)?,
+    };
+
+    build(map, registry)
+}
+
+pub fn is_tiled(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|e| e.to_str()),
+        Some("tmx" | "tmj" | "json")
+    )
+}
+
+fn unsupported(feature: impl AsRef<str>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unsupported Tiled feature: {}", feature.as_ref()),
+    )
+}
+
+fn invalid(message: impl AsRef<str>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid Tiled map: {}", message.as_ref()),
+    )
+}
+
+// Both formats are read into this first.
+struct Map {
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+    tilesets: Vec<Tileset>,
+    layers: Vec<MapLayer>,
+}
+
+struct Tileset {
+    first_gid: u32,
+    // Registry tile name per local tile id.
+    names: HashMap<u32, String>,
+}
+
+enum MapLayer {
+    Tiles {
+        name: String,
+        z: usize,
+        gids: Vec<u32>,
+    },
+    Objects {
+        z: usize,
+        objects: Vec<Object>,
+    },
+}
+
+struct Object {
+    class: String,
+    name: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+fn build(map: Map, registry: &TileRegistry) -> io::Result<Imported> {
+    let depth = map
+        .layers
+        .iter()
+        .map(|layer| match layer {
+            MapLayer::Tiles { z, .. } | MapLayer::Objects { z, .. } => z + 1,
+        })
+        .max()
+        .unwrap_or(1);
+    if depth > MAX_DEPTH {
+        return Err(unsupported(format!(
+            "z-level {}, at most {} levels fit",
+            depth - 1,
+            MAX_DEPTH
+        )));
+    }
+    let cells = map
+        .width
+        .checked_mul(map.height)
+        .and_then(|len| len.checked_mul(depth))
+        .filter(|&cells| cells <= MAX_CELLS);
+    let Some(cells) = cells else {
+        return Err(unsupported(format!(
+            "{}x{} map with {} levels, at most {} cells fit",
+            map.width, map.height, depth, MAX_CELLS
+        )));
+    };
+    let level_len = map.width * map.height;
+    if !(map.tile_width > 0.0 && map.tile_height > 0.0) {
+        return Err(invalid(format!(
+            "tile size {}x{}",
+            map.tile_width, map.tile_height
+        )));
+    }
+
+    let mut layers = LayerKind::ALL
+        .into_iter()
+        .map(|_| Layer::new(cells))
+        .collect::<Vec<_>>();
+    let mut spawns = vec![];
+    let mut zones = vec![];
+
+    // Tiled objects are placed in pixels of
+    // the map's own tile size.
+    let scale = vec2(TILE_SIZE / map.tile_width, TILE_SIZE / map.tile_height);
+
+    for layer in &map.layers {
+        match layer {
+            MapLayer::Tiles { name, z, gids } => {
+                let kind = match LayerKind::from_name(name) {
+                    None => {
+                        return Err(unsupported(format!(
+                            "tile layer \"{}\", expected floor, structure or decoration",
+                            name
+                        )))
+                    }
+                    Some(kind) => kind,
+                };
+                if gids.len() != level_len {
+                    return Err(invalid(format!("layer \"{}\" has the wrong size", name)));
+                }
+                for (i, &gid) in gids.iter().enumerate() {
+                    let tile = resolve(&map.tilesets, registry, gid)?;
+                    layers[kind as usize].tiles[z * level_len + i] = tile;
+                }
+            }
+            MapLayer::Objects { z, objects } => {
+                for object in objects {
+                    let p = vec2(object.x, object.y) * scale;
+                    match object.class.as_str() {
+                        "spawn" => spawns.push(Position { p, z: *z }),
+                        "zone" => zones.push(Zone {
+                            name: object.name.clone(),
+                            z: *z,
+                            rect: Rect::new(
+                                p.x,
+                                p.y,
+                                object.width * scale.x,
+                                object.height * scale.y,
+                            ),
+                        }),
+                        class => {
+                            return Err(unsupported(format!(
+                                "object class \"{}\", expected spawn or zone",
+                                class
+                            )))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(Imported {
+        layers,
+        width: map.width,
+        height: map.height,
+        spawns,
+        zones,
+    })
+}
+
+fn resolve(tilesets: &[Tileset], registry: &TileRegistry, gid: u32) -> io::Result<Option<Tile>> {
+    if gid & GID_FLAGS != 0 {
+        return Err(unsupported("flipped or rotated tiles"));
+    }
+    if gid == 0 {
+        return Ok(None);
+    }
+
+    let Some(tileset) = tilesets
+        .iter()
+        .filter(|tileset| tileset.first_gid <= gid)
+        .max_by_key(|tileset| tileset.first_gid)
+    else {
+        return Err(invalid(format!("gid {} is not in any tileset", gid)));
+    };
+
+    let local = gid - tileset.first_gid;
+    let tile = match tileset.names.get(&local) {
+        Some(name) => registry.by_name(name),
+        None => u8::try_from(local)
+            .ok()
+            .filter(|&id| registry.contains(id))
+            .map(Tile),
+    };
+
+    match tile {
+        Some(tile) => Ok(Some(tile)),
+        None => Err(invalid(format!("gid {} does not map to a known tile", gid))),
+    }
+}
+
+fn check_map(orientation: &str, infinite: bool) -> io::Result<()> {
+    if orientation != "orthogonal" {
+        return Err(unsupported(format!("{} orientation", orientation)));
+    }
+    if infinite {
+        return Err(unsupported("infinite maps"));
+    }
+    Ok(())
+}
+
+fn parse_csv(data: &str) -> io::Result<Vec<u32>> {
+    data.split(',')
+        .map(str::trim)
+        .filter(|gid| !gid.is_empty())
+        .map(|gid| {
+            gid.parse::<u32>()
+                .map_err(|_| invalid(format!("bad gid {}", gid)))
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+struct TmjMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    orientation: String,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    tilesets: Vec<TmjTileset>,
+    #[serde(default)]
+    layers: Vec<TmjLayer>,
+}
+
+#[derive(Deserialize)]
+struct TmjTileset {
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(default)]
+    tiles: Vec<TmjTile>,
+}
+
+#[derive(Deserialize)]
+struct TmjTile {
+    id: u32,
+    // Called type before Tiled 1.9.
+    #[serde(default, alias = "type")]
+    class: String,
+    #[serde(default)]
+    properties: Vec<TmjProperty>,
+}
+
+#[derive(Deserialize)]
+struct TmjProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct TmjLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    // A string when encoded, so it is only
+    // read once the encoding is known.
+    #[serde(default)]
+    data: serde_json::Value,
+    encoding: Option<String>,
+    compression: Option<String>,
+    #[serde(default)]
+    objects: Vec<TmjObject>,
+    #[serde(default)]
+    properties: Vec<TmjProperty>,
+}
+
+#[derive(Deserialize)]
+struct TmjObject {
+    #[serde(default, alias = "type")]
+    class: String,
+    #[serde(default)]
+    name: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+}
+
+fn tmj_z(properties: &[TmjProperty]) -> io::Result<usize> {
+    match properties.iter().find(|p| p.name == "z") {
+        Some(p) => p
+            .value
+            .as_u64()
+            .map(|z| z as usize)
+            .ok_or_else(|| invalid("z must be a non-negative integer")),
+        None => Ok(0),
+    }
+}
+
+fn parse_tmj(s: &str) -> io::Result<Map> {
+    let map: TmjMap = serde_json::from_str(s).map_err(|err| invalid(err.to_string()))?;
+    check_map(&map.orientation, map.infinite)?;
+
+    let tilesets = map
+        .tilesets
+        .into_iter()
+        .map(|tileset| {
+            if let Some(source) = tileset.source {
+                return Err(unsupported(format!("external tileset {}", source)));
+            }
+            let names = tileset
+                .tiles
+                .into_iter()
+                .filter_map(|tile| {
+                    let property = tile
+                        .properties
+                        .iter()
+                        .find(|p| p.name == "tile")
+                        .and_then(|p| p.value.as_str().map(str::to_string));
+                    let name = property.unwrap_or(tile.class);
+                    (!name.is_empty()).then_some((tile.id, name))
+                })
+                .collect();
+            Ok(Tileset {
+                first_gid: tileset.firstgid,
+                names,
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let layers = map
+        .layers
+        .into_iter()
+        .map(|layer| {
+            let z = tmj_z(&layer.properties)?;
+            match layer.kind.as_str() {
+                "tilelayer" => {
+                    if let Some(encoding) = layer.encoding.filter(|e| e != "csv") {
+                        return Err(unsupported(format!("{} layer encoding", encoding)));
+                    }
+                    if let Some(compression) = layer.compression.filter(|c| !c.is_empty()) {
+                        return Err(unsupported(format!("{} layer compression", compression)));
+                    }
+                    let gids = serde_json::from_value(layer.data).map_err(|_| {
+                        invalid(format!(
+                            "layer \"{}\" data is not a list of gids",
+                            layer.name
+                        ))
+                    })?;
+                    Ok(MapLayer::Tiles {
+                        name: layer.name,
+                        z,
+                        gids,
+                    })
+                }
+                "objectgroup" => Ok(MapLayer::Objects {
+                    z,
+                    objects: layer
+                        .objects
+                        .into_iter()
+                        .map(|o| Object {
+                            class: o.class,
+                            name: o.name,
+                            x: o.x,
+                            y: o.y,
+                            width: o.width,
+                            height: o.height,
+                        })
+                        .collect(),
+                }),
+                kind => Err(unsupported(format!("{} layers", kind))),
+            }
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(Map {
+        width: map.width,
+        height: map.height,
+        tile_width: map.tilewidth,
+        tile_height: map.tileheight,
+        tilesets,
+        layers,
+    })
+}
+
+fn attr<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> io::Result<&'a str> {
+    node.attribute(name)
+        .ok_or_else(|| invalid(format!("<{}> is missing {}", node.tag_name().name(), name)))
+}
+
+fn attr_num<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> io::Result<T> {
+    attr(node, name)?
+        .parse()
+        .map_err(|_| invalid(format!("{} is not a number", name)))
+}
+
+fn attr_or<T: std::str::FromStr>(node: roxmltree::Node, name: &str, default: T) -> io::Result<T> {
+    match node.attribute(name) {
+        Some(_) => attr_num(node, name),
+        None => Ok(default),
+    }
+}
+
+fn tmx_property<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .filter(|n| n.has_tag_name("properties"))
+        .flat_map(|n| n.children())
+        .find(|n| n.has_tag_name("property") && n.attribute("name") == Some(name))
+        .and_then(|n| n.attribute("value"))
+}
+
+fn tmx_z(node: roxmltree::Node) -> io::Result<usize> {
+    match tmx_property(node, "z") {
+        Some(z) => z
+            .parse()
+            .map_err(|_| invalid("z must be a non-negative integer")),
+        None => Ok(0),
+    }
+}
+
+fn parse_tmx(s: &str) -> io::Result<Map> {
+    let doc = roxmltree::Document::parse(s).map_err(|err| invalid(err.to_string()))?;
+    let root = doc.root_element();
+
+    check_map(
+        root.attribute("orientation").unwrap_or("orthogonal"),
+        attr_or(root, "infinite", 0)? != 0,
+    )?;
+
+    let mut tilesets = vec![];
+    let mut layers = vec![];
+
+    for node in root.children().filter(|n| n.is_element()) {
+        match node.tag_name().name() {
+            "tileset" => {
+                if let Some(source) = node.attribute("source") {
+                    return Err(unsupported(format!("external tileset {}", source)));
+                }
+                let mut names = HashMap::new();
+                for tile in node.children().filter(|n| n.has_tag_name("tile")) {
+                    let class = tile
+                        .attribute("class")
+                        .or_else(|| tile.attribute("type"))
+                        .unwrap_or_default();
+                    let name = tmx_property(tile, "tile").unwrap_or(class);
+                    if !name.is_empty() {
+                        names.insert(attr_num(tile, "id")?, name.to_string());
+                    }
+                }
+                tilesets.push(Tileset {
+                    first_gid: attr_num(node, "firstgid")?,
+                    names,
+                });
+            }
+            "layer" => {
+                let Some(data) = node.children().find(|n| n.has_tag_name("data")) else {
+                    return Err(invalid("tile layer without data"));
+                };
+                let gids = match data.attribute("encoding") {
+                    Some("csv") => parse_csv(data.text().unwrap_or_default())?,
+                    Some(encoding) => {
+                        return Err(unsupported(format!("{} layer encoding", encoding)))
+                    }
+                    None => data
+                        .children()
+                        .filter(|n| n.has_tag_name("tile"))
+                        .map(|n| attr_or(n, "gid", 0))
+                        .collect::<io::Result<Vec<u32>>>()?,
+                };
+                layers.push(MapLayer::Tiles {
+                    name: attr(node, "name")?.to_string(),
+                    z: tmx_z(node)?,
+                    gids,
+                });
+            }
+            "objectgroup" => {
+                let objects = node
+                    .children()
+                    .filter(|n| n.has_tag_name("object"))
+                    .map(|o| {
+                        Ok(Object {
+                            class: o
+                                .attribute("class")
+                                .or_else(|| o.attribute("type"))
+                                .unwrap_or_default()
+                                .to_string(),
+                            name: o.attribute("name").unwrap_or_default().to_string(),
+                            x: attr_num(o, "x")?,
+                            y: attr_num(o, "y")?,
+                            width: attr_or(o, "width", 0.0)?,
+                            height: attr_or(o, "height", 0.0)?,
+                        })
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                layers.push(MapLayer::Objects {
+                    z: tmx_z(node)?,
+                    objects,
+                });
+            }
+            "properties" | "editorsettings" => (),
+            tag => return Err(unsupported(format!("<{}> elements", tag))),
+        }
+    }
+
+    Ok(Map {
+        width: attr_num(root, "width")?,
+        height: attr_num(root, "height")?,
+        tile_width: attr_num(root, "tilewidth")?,
+        tile_height: attr_num(root, "tileheight")?,
+        tilesets,
+        layers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::registry;
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="3" height="2" tilewidth="16" tileheight="16" infinite="0">
+ <tileset firstgid="1" name="werfs" tilewidth="16" tileheight="16">
+  <tile id="0" type="ground"/>
+  <tile id="1">
+   <properties>
+    <property name="tile" value="wall"/>
+   </properties>
+  </tile>
+ </tileset>
+ <layer id="1" name="floor" width="3" height="2">
+  <data encoding="csv">
+1,1,1,
+1,1,0
+</data>
+ </layer>
+ <layer id="2" name="structure" width="3" height="2">
+  <data encoding="csv">
+2,0,0,
+0,0,0
+</data>
+ </layer>
+ <objectgroup id="3" name="things">
+  <object id="1" type="spawn" x="32" y="16"/>
+  <object id="2" name="mine" type="zone" x="0" y="0" width="32" height="16"/>
+ </objectgroup>
+</map>"#;
+
+    #[test]
+    fn tmx_maps_to_layers_spawns_and_zones() {
+        let map = build(parse_tmx(TMX).unwrap(), &registry()).unwrap();
+
+        let floor = &map.layers[LayerKind::Floor as usize].tiles;
+        let structure = &map.layers[LayerKind::Structure as usize].tiles;
+        assert_eq!((map.width, map.height), (3, 2));
+        assert_eq!(floor[0], Some(Tile(0)));
+        assert_eq!(floor[5], None);
+        assert_eq!(structure[0], Some(Tile(1)));
+
+        let scale = TILE_SIZE / 16.0;
+        assert_eq!(map.spawns[0].p, vec2(32.0, 16.0) * scale);
+        assert_eq!(map.zones[0].name, "mine");
+        assert_eq!(map.zones[0].rect.w, 32.0 * scale);
+    }
+
+    #[test]
+    fn tmj_uses_tile_ids_without_a_class() {
+        let tmj = r#"{
+            "width": 2, "height": 1, "tilewidth": 16, "tileheight": 16,
+            "orientation": "orthogonal", "infinite": false,
+            "tilesets": [{ "firstgid": 1 }],
+            "layers": [
+                { "type": "tilelayer", "name": "structure", "data": [2, 0],
+                  "properties": [{ "name": "z", "type": "int", "value": 1 }] }
+            ]
+        }"#;
+        let map = build(parse_tmj(tmj).unwrap(), &registry()).unwrap();
+
+        let structure = &map.layers[LayerKind::Structure as usize].tiles;
+        assert_eq!(structure.len(), 4);
+        assert_eq!(structure[2], Some(Tile(1)));
+    }
+
+    #[test]
+    fn unsupported_features_are_reported() {
+        let infinite = TMX.replace(r#"infinite="0""#, r#"infinite="1""#);
+        let base64 = TMX.replace(r#"encoding="csv""#, r#"encoding="base64""#);
+        let flipped = TMX.replace("2,0,0,", "2147483650,0,0,");
+
+        let deep = TMX.replace(
+            r#"name="floor" width="3" height="2">"#,
+            r#"name="floor" width="3" height="2"><properties><property name="z" value="99999999"/></properties>"#,
+        );
+
+        // Too many cells, or more than fit a
+        // usize.
+        let huge = TMX.replace(
+            r#"width="3" height="2" tilewidth"#,
+            r#"width="65536" height="65536" tilewidth"#,
+        );
+        let overflow = TMX.replace(
+            r#"width="3" height="2" tilewidth"#,
+            &format!(r#"width="{}" height="2" tilewidth"#, usize::MAX),
+        );
+
+        for tmx in [infinite, base64, flipped, deep, huge, overflow] {
+            let err = parse_tmx(&tmx)
+                .and_then(|map| build(map, &registry()))
+                .err()
+                .expect("should fail");
+            assert!(
+                err.to_string().starts_with("unsupported Tiled feature"),
+                "{}",
+                err
+            );
+        }
+
+        let tmj = |layer: &str| {
+            format!(
+                r#"{{
+                    "width": 2, "height": 1, "tilewidth": 16, "tileheight": 16,
+                    "orientation": "orthogonal",
+                    "layers": [{{ "type": "tilelayer", "name": "floor", {} }}]
+                }}"#,
+                layer
+            )
+        };
+        let base64 = tmj(r#""data": "AQAAAAEAAAA=", "encoding": "base64""#);
+        let zlib =
+            tmj(r#""data": "eJxjZGBgAAAACAAC", "encoding": "base64", "compression": "zlib""#);
+        let deep = tmj(
+            r#""data": [1, 1], "properties": [{ "name": "z", "type": "int", "value": 99999999 }]"#,
+        );
+
+        for tmj in [base64, zlib, deep] {
+            let err = parse_tmj(&tmj)
+                .and_then(|map| build(map, &registry()))
+                .err()
+                .expect("should fail");
+            assert!(
+                err.to_string().starts_with("unsupported Tiled feature"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn tile_sizes_have_to_be_positive() {
+        for size in [r#"tilewidth="0""#, r#"tileheight="-16""#] {
+            let from = &size[..size.find('=').unwrap()];
+            let tmx = TMX.replacen(&format!(r#"{}="16""#, from), size, 1);
+            let err = parse_tmx(&tmx)
+                .and_then(|map| build(map, &registry()))
+                .err()
+                .expect("should fail");
+            assert!(err.to_string().contains("tile size"), "{}", err);
+        }
+    }
+}
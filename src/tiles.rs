@@ -1,193 +1,612 @@
-use ::rand::{rngs::ThreadRng, Rng};
+use std::{
+    collections::{HashSet, VecDeque},
+    io,
+};
+
+use ::rand::rngs::StdRng;
 use macroquad::prelude::*;
 
 use crate::{
-    constants::{TILESHEET_WIDTH, TILE_SIZE},
-    tile::Tile,
-    utils::xy_to_index,
+    assets::Sheet,
+    autotile::{Autotile, NEIGHBOURS},
+    constants::TILE_SIZE,
+    history::{Cell, Change, ChangeSet},
+    layer::{Layer, LayerKind},
+    tile::{Climb, Tile, TileRegistry},
+    utils::{index_to_xyz, xyz_to_index},
 };
 
+// Placed beneath anything that isn't
+// a floor tile itself.
+pub const DEFAULT_FLOOR: &str = "ground";
+
+// How many open levels can be seen through
+// before the view stops, each one drawn
+// darker than the one above it.
+const VISIBLE_BELOW: usize = 3;
+
+// Z-levels are stacked one after the other,
+// with z = 0 at the bottom.
 #[derive(Debug)]
 pub struct Tiles {
-    pub tiles: Vec<Tile>,
+    // One per LayerKind, in the same order.
+    pub layers: Vec<Layer>,
     pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    pub registry: TileRegistry,
+    autotile: Autotile,
+    // Every change since the last call
+    // to take_changes.
+    changes: ChangeSet,
+    // Tile changes, undo included, since the
+    // last call to take_changed.
+    changed: Vec<Change>,
 }
 
-#[allow(dead_code)]
 impl Tiles {
-    pub fn new(rng: &mut ThreadRng, tiles: Vec<u8>, width: usize) -> Self {
-        let tiles = tiles
-            .iter()
-            .map(|t| match t {
-                0 => Tile::Ground01,
-                1 => Tile::WallTop01,
-                _ => Tile::Ground01,
-            })
+    pub fn new(
+        rng: &mut StdRng,
+        tiles: Vec<u8>,
+        width: usize,
+        height: usize,
+        registry: TileRegistry,
+        autotile: Autotile,
+    ) -> io::Result<Self> {
+        if tiles.is_empty() || !tiles.len().is_multiple_of(width * height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} tiles do not fit {}x{} levels",
+                    tiles.len(),
+                    width,
+                    height
+                ),
+            ));
+        }
+
+        if let Some(id) = tiles.iter().find(|&&id| !registry.contains(id)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown tile id {}", id),
+            ));
+        }
+
+        let Some(floor) = registry.by_name(DEFAULT_FLOOR) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("missing default floor tile {}", DEFAULT_FLOOR),
+            ));
+        };
+
+        let mut layers = LayerKind::ALL
+            .into_iter()
+            .map(|_| Layer::new(tiles.len()))
             .collect::<Vec<_>>();
 
-        let mut s = Self { tiles, width };
+        for (i, id) in tiles.into_iter().enumerate() {
+            let tile = Tile(id);
+            let kind = registry.get(tile).layer;
+            if kind != LayerKind::Floor {
+                layers[LayerKind::Floor as usize].tiles[i] = Some(floor);
+            }
+            layers[kind as usize].tiles[i] = Some(tile);
+        }
+
+        Self::from_layers(rng, layers, width, height, registry, autotile)
+    }
+
+    // Takes one layer per LayerKind, with tiles
+    // already in place. Sprites are picked here.
+    pub fn from_layers(
+        rng: &mut StdRng,
+        mut layers: Vec<Layer>,
+        width: usize,
+        height: usize,
+        registry: TileRegistry,
+        autotile: Autotile,
+    ) -> io::Result<Self> {
+        let len = layers.first().map_or(0, |layer| layer.tiles.len());
+        if layers.len() != LayerKind::ALL.len()
+            || layers.iter().any(|layer| layer.tiles.len() != len)
+            || len == 0
+            || !len.is_multiple_of(width * height)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("layers do not fit {}x{} levels", width, height),
+            ));
+        }
 
-        for i in 0..s.tiles.len() {
-            s.update_tile(rng, i);
+        for i in 0..len {
+            if layers
+                .iter()
+                .any(|layer| is_open_tile(&registry, layer.tiles[i]))
+            {
+                for layer in &mut layers {
+                    layer.tiles[i] = None;
+                }
+            }
         }
 
-        s
+        let mut s = Self {
+            layers,
+            width,
+            height,
+            depth: len / (width * height),
+            registry,
+            autotile,
+            changes: ChangeSet::default(),
+            changed: vec![],
+        };
+
+        for kind in LayerKind::ALL {
+            for i in 0..s.len() {
+                s.update_tile(rng, kind, i);
+            }
+        }
+
+        // Loading isn't something to undo.
+        s.changes = ChangeSet::default();
+        s.changed.clear();
+
+        Ok(s)
     }
 
-    pub fn update_tile(&mut self, rng: &mut ThreadRng, index: usize) {
-        if !self.tile_is_wall(index) {
+    pub fn len(&self) -> usize {
+        self.layer(LayerKind::Floor).tiles.len()
+    }
+
+    // Never true, levels can't be empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn xyz(&self, index: usize) -> (i32, i32, i32) {
+        let (x, y, z) = index_to_xyz(index, self.width, self.height);
+        (x as i32, y as i32, z as i32)
+    }
+
+    pub fn layer(&self, kind: LayerKind) -> &Layer {
+        &self.layers[kind as usize]
+    }
+
+    fn layer_mut(&mut self, kind: LayerKind) -> &mut Layer {
+        &mut self.layers[kind as usize]
+    }
+
+    pub fn tile(&self, kind: LayerKind, index: usize) -> Option<Tile> {
+        self.layer(kind).tiles[index]
+    }
+
+    pub fn update_tile(&mut self, rng: &mut StdRng, kind: LayerKind, index: usize) {
+        let Some(tile) = self.tile(kind, index) else {
             return;
+        };
+
+        let sprite = if self.registry.get(tile).autotile {
+            self.autotile.pick(rng, self.autotile_mask(kind, index))
+        } else {
+            self.registry.random_sprite(rng, tile)
+        };
+
+        self.write(
+            kind,
+            index,
+            Cell {
+                tile: Some(tile),
+                sprite,
+            },
+        );
+    }
+
+    // Autotiled tiles pick their sprite based on
+    // the tiles around them, so any change needs
+    // to be reflected in the surrounding tiles
+    // as well.
+    pub fn update_neighbours(&mut self, rng: &mut StdRng, kind: LayerKind, index: usize) {
+        let (x, y, z) = self.xyz(index);
+        for (dx, dy, _) in NEIGHBOURS {
+            if let Some(i) = self.index_at(x + dx, y + dy, z) {
+                if self.is_autotiled(kind, i) {
+                    self.update_tile(rng, kind, i);
+                }
+            }
         }
+    }
 
-        self.tiles[index] = self.random_wall_top(rng);
+    // Places the tile in the layer it belongs to.
+    // Floor tiles also clear the structure above
+    // them, which is how walls are dug out, and
+    // open tiles clear every layer.
+    pub fn set_tile(&mut self, rng: &mut StdRng, index: usize, t: Tile) {
+        let kind = self.registry.get(t).layer;
+        self.put(rng, kind, index, Some(t));
+        if kind == LayerKind::Floor {
+            self.put(rng, LayerKind::Structure, index, None);
+        }
+    }
 
-        if let Some(b) = self.tile_below(index) {
-            if b.is_ground() {
-                self.tiles[index] = self.random_wall_side(rng);
+    pub fn clear_tile(&mut self, rng: &mut StdRng, kind: LayerKind, index: usize) {
+        self.put(rng, kind, index, None);
+    }
+
+    // Clears a diggable structure, keeping the
+    // floor under it or putting down the
+    // default one. Returns what was dug out.
+    pub fn dig(&mut self, rng: &mut StdRng, index: usize) -> Option<Tile> {
+        let dug = self
+            .tile(LayerKind::Structure, index)
+            .filter(|&t| self.registry.get(t).diggable)?;
+        let floor = self
+            .tile(LayerKind::Floor, index)
+            .or_else(|| self.registry.by_name(DEFAULT_FLOOR));
+        match floor {
+            Some(floor) => self.set_tile(rng, index, floor),
+            None => self.clear_tile(rng, LayerKind::Structure, index),
+        }
+        Some(dug)
+    }
+
+    fn put(&mut self, rng: &mut StdRng, kind: LayerKind, index: usize, t: Option<Tile>) {
+        if is_open_tile(&self.registry, t) {
+            for kind in LayerKind::ALL {
+                self.put(rng, kind, index, None);
             }
+            return;
         }
+        let sprite = self.layer(kind).sprites[index];
+        self.write(kind, index, Cell { tile: t, sprite });
+        self.update_tile(rng, kind, index);
+        self.update_neighbours(rng, kind, index);
     }
 
-    pub fn update_neighbours(&mut self, rng: &mut ThreadRng, index: usize) {
-        if self.tiles[index].is_wall() {
+    pub fn cell(&self, kind: LayerKind, index: usize) -> Cell {
+        let layer = self.layer(kind);
+        Cell {
+            tile: layer.tiles[index],
+            sprite: layer.sprites[index],
+        }
+    }
+
+    // All gameplay tile changes pass through
+    // here, so that they can be undone.
+    fn write(&mut self, kind: LayerKind, index: usize, cell: Cell) {
+        let before = self.cell(kind, index);
+        if before == cell {
             return;
         }
+        self.changes.changes.push(Change {
+            kind,
+            index,
+            before,
+            after: cell,
+        });
+        self.write_untracked(kind, index, cell);
+    }
 
-        self.tiles[index] = self.random_ground(rng);
+    fn write_untracked(&mut self, kind: LayerKind, index: usize, cell: Cell) {
+        let before = self.cell(kind, index);
+        if before.tile != cell.tile {
+            self.changed.push(Change {
+                kind,
+                index,
+                before,
+                after: cell,
+            });
+        }
+        let layer = self.layer_mut(kind);
+        layer.tiles[index] = cell.tile;
+        layer.sprites[index] = cell.sprite;
+    }
 
-        if let Some(top) = self.tile_above(index) {
-            if top.is_wall() {
-                self.tiles[index - self.width] = self.random_wall_side(rng);
-            }
+    pub fn take_changes(&mut self) -> ChangeSet {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn take_changed(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn revert(&mut self, set: &ChangeSet) {
+        for change in set.changes.iter().rev() {
+            self.write_untracked(change.kind, change.index, change.before);
         }
     }
 
-    pub fn draw(&self, texture: &Texture2D) {
-        for (i, tile) in self.tiles.iter().enumerate() {
-            let x = (i % self.width) as f32 * TILE_SIZE;
-            let y = (i / self.width) as f32 * TILE_SIZE;
-            let tile_i = *tile as u8;
-            let tileset_x = (tile_i % TILESHEET_WIDTH as u8) as f32 * TILE_SIZE;
-            let tileset_y = (tile_i / TILESHEET_WIDTH as u8) as f32 * TILE_SIZE;
-            draw_texture_ex(
-                texture,
-                x,
-                y,
-                WHITE,
-                DrawTextureParams {
-                    dest_size: Some(Vec2 {
-                        x: TILE_SIZE,
-                        y: TILE_SIZE,
-                    }),
-                    source: Some(Rect {
-                        x: tileset_x,
-                        y: tileset_y,
-                        w: TILE_SIZE,
-                        h: TILE_SIZE,
-                    }),
-                    ..Default::default()
-                },
-            );
-        }
-    }
-
-    pub fn set_square(&mut self, rng: &mut ThreadRng, x: usize, y: usize, size: usize, t: Tile) {
-        for x in x..x + size {
-            for y in y..y + size {
-                self.tiles[x + y * self.width] = t;
-            }
+    pub fn reapply(&mut self, set: &ChangeSet) {
+        for change in &set.changes {
+            self.write_untracked(change.kind, change.index, change.after);
         }
-        for x in x..x + size {
-            for y in y..y + size {
-                let index = xy_to_index(x, y, self.width);
-                self.update_neighbours(rng, index);
+    }
+
+    pub fn autotile_mask(&self, kind: LayerKind, index: usize) -> u8 {
+        let (x, y, z) = self.xyz(index);
+        let mut mask = 0;
+        for (dx, dy, bit) in NEIGHBOURS {
+            let same = match self.index_at(x + dx, y + dy, z) {
+                Some(i) => self.is_autotiled(kind, i),
+                None => true,
+            };
+            if same {
+                mask |= bit;
             }
         }
+        mask
     }
 
-    pub fn tile_above(&self, index: usize) -> Option<Tile> {
-        if index < self.width {
-            return None;
-        }
-        let index = index - self.width;
-        if index >= self.tiles.len() {
-            return None;
+    // Open cells have nothing to stand on.
+    pub fn is_blocked(&self, index: usize) -> bool {
+        match self.tile(LayerKind::Structure, index) {
+            Some(t) => self.registry.is_blocked(t),
+            None => self.tile(LayerKind::Floor, index).is_none(),
         }
-        Some(self.tiles[index])
     }
 
-    pub fn tile_below(&self, index: usize) -> Option<Tile> {
-        let index = index + self.width;
-        if index >= self.tiles.len() {
-            return None;
+    // What a cell mostly looks like, the
+    // structure if there is one.
+    pub fn top_tile(&self, index: usize) -> Option<Tile> {
+        self.tile(LayerKind::Structure, index)
+            .or_else(|| self.tile(LayerKind::Floor, index))
+    }
+
+    pub fn is_open(&self, index: usize) -> bool {
+        self.tile(LayerKind::Floor, index).is_none()
+            && self.tile(LayerKind::Structure, index).is_none()
+    }
+
+    pub fn climb(&self, index: usize) -> Climb {
+        match self.tile(LayerKind::Structure, index) {
+            Some(t) => self.registry.get(t).climb,
+            None => Climb::None,
         }
-        Some(self.tiles[index])
     }
 
-    pub fn tile_at_right(&self, index: usize) -> Option<Tile> {
-        let index = index + 1;
-        if index >= self.tiles.len() {
-            return None;
+    // The most expensive tile of the walkable
+    // layers decides the cost.
+    pub fn cost(&self, index: usize) -> u32 {
+        [
+            LayerKind::Floor,
+            LayerKind::Structure,
+            LayerKind::Decoration,
+        ]
+        .into_iter()
+        .filter_map(|kind| self.tile(kind, index))
+        .map(|t| self.registry.get(t).cost)
+        .max()
+        .unwrap_or(1)
+    }
+
+    fn is_autotiled(&self, kind: LayerKind, index: usize) -> bool {
+        match self.tile(kind, index) {
+            Some(t) => self.registry.get(t).autotile,
+            None => false,
         }
-        Some(self.tiles[index])
     }
 
-    pub fn tile_at_left(&self, index: usize) -> Option<Tile> {
-        if index == 0 {
+    pub fn index_at(&self, x: i32, y: i32, z: i32) -> Option<usize> {
+        if x < 0 || y < 0 || z < 0 {
             return None;
         }
-        let index = index - 1;
-        if index >= self.tiles.len() {
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        if x >= self.width || y >= self.height || z >= self.depth {
             return None;
         }
-        Some(self.tiles[index])
+        Some(xyz_to_index(x, y, z, self.width, self.height))
     }
 
-    fn tile_is_wall(&self, index: usize) -> bool {
-        let tile = self.tiles[index];
-        tile.is_wall()
+    // Closest cell on the same z-level that is
+    // not blocked, by walking distance ignoring
+    // walls.
+    pub fn nearest_unblocked(&self, index: usize) -> Option<usize> {
+        // Usually only a few cells are looked at,
+        // not worth a flag for every one.
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([index]);
+
+        while let Some(i) = queue.pop_front() {
+            if !seen.insert(i) {
+                continue;
+            }
+            if !self.is_blocked(i) {
+                return Some(i);
+            }
+
+            let (x, y, z) = self.xyz(i);
+            for (dx, dy) in [(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                if let Some(n) = self.index_at(x + dx, y + dy, z) {
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        None
     }
 
-    fn random_ground(&self, rng: &mut ThreadRng) -> Tile {
-        if rng.gen_ratio(90, 100) {
-            return Tile::Ground01;
+    // Draws level z, looking through open cells
+    // to the levels below, dimmed the further
+    // down they are.
+    pub fn draw(&self, sheet: &Sheet, z: usize) {
+        let level_len = self.width * self.height;
+        for i in 0..level_len {
+            let x = (i % self.width) as f32 * TILE_SIZE;
+            let y = (i / self.width) as f32 * TILE_SIZE;
+
+            for below in 0..=z.min(VISIBLE_BELOW) {
+                let index = i + (z - below) * level_len;
+                if self.is_open(index) {
+                    continue;
+                }
+                let shade = 0.5_f32.powi(below as i32);
+                let tint = Color::new(shade, shade, shade, 1.0);
+                for layer in &self.layers {
+                    self.draw_sprite(sheet, layer, index, x, y, tint);
+                }
+                break;
+            }
         }
+    }
 
-        match rng.gen_range(0..=3) {
-            0 => Tile::Ground01,
-            1 => Tile::Ground02,
-            2 => Tile::Ground03,
-            _ => Tile::Ground01,
+    fn draw_sprite(&self, sheet: &Sheet, layer: &Layer, index: usize, x: f32, y: f32, tint: Color) {
+        if layer.tiles[index].is_none() {
+            return;
         }
+        draw_texture_ex(
+            &sheet.texture,
+            x,
+            y,
+            tint,
+            DrawTextureParams {
+                dest_size: Some(Vec2 {
+                    x: TILE_SIZE,
+                    y: TILE_SIZE,
+                }),
+                source: Some(sheet.source(layer.sprites[index])),
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn set_square(
+        &mut self,
+        rng: &mut StdRng,
+        x: usize,
+        y: usize,
+        z: usize,
+        size: usize,
+        t: Tile,
+    ) {
+        self.set_rect(rng, x, y, z, size, size, t);
     }
 
-    fn random_wall_top(&self, rng: &mut ThreadRng) -> Tile {
-        if rng.gen_ratio(85, 100) {
-            return Tile::WallTop01;
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_rect(
+        &mut self,
+        rng: &mut StdRng,
+        x: usize,
+        y: usize,
+        z: usize,
+        w: usize,
+        h: usize,
+        t: Tile,
+    ) {
+        for x in x..(x + w).min(self.width) {
+            for y in y..(y + h).min(self.height) {
+                self.set_tile(rng, xyz_to_index(x, y, z, self.width, self.height), t);
+            }
         }
+    }
+}
+
+// Open tiles are never kept in a layer, they
+// leave the cell empty on every layer, so
+// it's a hole however they were placed.
+fn is_open_tile(registry: &TileRegistry, t: Option<Tile>) -> bool {
+    t.is_some_and(|t| registry.get(t).open)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::SeedableRng;
+
+    use super::*;
+    use crate::test_util;
 
-        match rng.gen_range(0..=5) {
-            0 => Tile::WallTop01,
-            1 => Tile::WallTop02,
-            2 => Tile::WallTop03,
-            3 => Tile::WallTop04,
-            4 => Tile::WallTop05,
-            _ => Tile::WallTop01,
+    const GROUND: Tile = Tile(0);
+    const WALL: Tile = Tile(1);
+    const STAIRS: Tile = Tile(2);
+    const OPEN: Tile = Tile(8);
+
+    #[test]
+    fn structures_sit_on_the_default_floor() {
+        let tiles = test_util::tiles(vec![0, 1, 2, 8], 4, 1);
+
+        assert_eq!(tiles.tile(LayerKind::Floor, 0), Some(GROUND));
+        assert_eq!(tiles.tile(LayerKind::Structure, 0), None);
+        for (i, t) in [(1, WALL), (2, STAIRS)] {
+            assert_eq!(tiles.tile(LayerKind::Floor, i), Some(GROUND));
+            assert_eq!(tiles.tile(LayerKind::Structure, i), Some(t));
+            assert_eq!(tiles.top_tile(i), Some(t));
         }
+        // The most expensive layer wins.
+        assert_eq!((tiles.cost(0), tiles.cost(2)), (1, 2));
+        assert!(tiles.is_open(3) && tiles.top_tile(3).is_none());
     }
 
-    fn random_wall_side(&self, rng: &mut ThreadRng) -> Tile {
-        if rng.gen_ratio(85, 100) {
-            return Tile::WallSide01;
+    #[test]
+    fn floors_replace_structures() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tiles = test_util::tiles(vec![1, 1, 2], 3, 1);
+
+        tiles.set_tile(&mut rng, 0, GROUND);
+        assert_eq!(tiles.top_tile(0), Some(GROUND));
+        assert_eq!(tiles.dig(&mut rng, 1), Some(WALL));
+        assert_eq!(tiles.top_tile(1), Some(GROUND));
+        // Stairs aren't diggable.
+        assert_eq!(tiles.dig(&mut rng, 2), None);
+        assert_eq!(tiles.top_tile(2), Some(STAIRS));
+    }
+
+    #[test]
+    fn open_tiles_empty_every_layer() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tiles = test_util::tiles(vec![0, 1, 2], 3, 1);
+
+        for i in 0..3 {
+            tiles.set_tile(&mut rng, i, OPEN);
+            assert!(tiles.is_open(i) && tiles.is_blocked(i), "{}", i);
         }
 
-        match rng.gen_range(0..=5) {
-            0 => Tile::WallSide01,
-            1 => Tile::WallSide02,
-            2 => Tile::WallSide03,
-            3 => Tile::WallSide04,
-            4 => Tile::WallSide05,
-            _ => Tile::WallSide01,
+        let mut layers = Vec::from(LayerKind::ALL.map(|_| Layer::new(2)));
+        layers[LayerKind::Floor as usize].tiles = vec![Some(GROUND), Some(GROUND)];
+        layers[LayerKind::Structure as usize].tiles = vec![Some(OPEN), None];
+        let (registry, autotile) = (test_util::registry(), Autotile::new(16, 16).unwrap());
+        let tiles = Tiles::from_layers(&mut rng, layers, 2, 1, registry, autotile).unwrap();
+        assert!(tiles.is_open(0) && !tiles.is_open(1));
+        assert_eq!(tiles.layer(LayerKind::Floor).tiles[0], None);
+    }
+
+    #[test]
+    fn structures_and_missing_floors_block() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tiles = test_util::tiles(vec![0, 1, 2, 8], 4, 1);
+
+        assert!(!tiles.is_blocked(0));
+        assert!(tiles.is_blocked(1));
+        assert!(!tiles.is_blocked(2));
+        assert!(tiles.is_blocked(3));
+
+        // A wall without a floor still blocks,
+        // stairs without one don't.
+        tiles.clear_tile(&mut rng, LayerKind::Floor, 1);
+        tiles.clear_tile(&mut rng, LayerKind::Floor, 2);
+        assert!(tiles.is_blocked(1));
+        assert!(!tiles.is_blocked(2));
+    }
+
+    #[test]
+    fn z_levels_follow_each_other() {
+        #[rustfmt::skip]
+        let tiles = test_util::tiles(
+            vec![
+                0, 0, 0,
+                0, 0, 1,
+                // z = 1
+                8, 8, 8,
+                8, 2, 8,
+            ],
+            3,
+            2,
+        );
+
+        assert_eq!(tiles.depth, 2);
+        assert_eq!(tiles.index_at(2, 1, 0), Some(5));
+        assert_eq!(tiles.index_at(1, 1, 1), Some(10));
+        assert_eq!(tiles.xyz(10), (1, 1, 1));
+        assert_eq!(tiles.top_tile(10), Some(STAIRS));
+        assert!(tiles.is_blocked(5) && tiles.is_open(6));
+        for (x, y, z) in [(-1, 0, 0), (3, 0, 0), (0, 2, 0), (0, 0, 2), (0, 0, -1)] {
+            assert_eq!(tiles.index_at(x, y, z), None);
         }
     }
 }
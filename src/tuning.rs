@@ -0,0 +1,287 @@
+use std::{
+    fs::{read_to_string, write},
+    io,
+    path::Path,
+};
+
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use toml_edit::DocumentMut;
+
+// Movement and collision values, loaded from
+// data/tuning.toml. Values missing from the
+// file keep their default.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tuning {
+    pub damping: f32,
+    pub stop_threshold: f32,
+    pub setback: f32,
+    pub collision_damping: f32,
+    pub waypoint_radius: f32,
+    pub steering_speed: f32,
+    pub max_speed: f32,
+    pub arrive_radius: f32,
+    pub separation: f32,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            damping: 0.96,
+            stop_threshold: 0.001,
+            setback: 1.8,
+            collision_damping: 0.85,
+            waypoint_radius: 50.0,
+            steering_speed: 4.0,
+            max_speed: 1.5,
+            arrive_radius: 24.0,
+            separation: 0.5,
+        }
+    }
+}
+
+// Name, the value, the range it is kept in
+// and how much one key press changes it in
+// the panel, in field order.
+type Field = (&'static str, fn(&mut Tuning) -> &mut f32, f32, f32, f32);
+
+const FIELDS: [Field; 9] = [
+    ("damping", |t| &mut t.damping, 0.0, 1.0, 0.005),
+    (
+        "stop_threshold",
+        |t| &mut t.stop_threshold,
+        0.0,
+        1.0,
+        0.0005,
+    ),
+    // Collision divides by it.
+    ("setback", |t| &mut t.setback, 0.1, 10.0, 0.02),
+    (
+        "collision_damping",
+        |t| &mut t.collision_damping,
+        0.0,
+        1.0,
+        0.01,
+    ),
+    (
+        "waypoint_radius",
+        |t| &mut t.waypoint_radius,
+        0.0,
+        1000.0,
+        5.0,
+    ),
+    (
+        "steering_speed",
+        |t| &mut t.steering_speed,
+        0.0,
+        100.0,
+        0.25,
+    ),
+    ("max_speed", |t| &mut t.max_speed, 0.0, 16.0, 0.1),
+    ("arrive_radius", |t| &mut t.arrive_radius, 0.0, 256.0, 2.0),
+    ("separation", |t| &mut t.separation, 0.0, 10.0, 0.05),
+];
+
+impl Tuning {
+    // Without a file the defaults are used.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match read_to_string(path) {
+            Ok(s) => Self::parse(&s),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let tuning: Self =
+            toml::from_str(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        tuning.check()?;
+        Ok(tuning)
+    }
+
+    // Only the values change, comments and
+    // anything else in the file are kept.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let s = match read_to_string(path) {
+            Ok(s) => s,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        write(path, self.update_file(&s)?)
+    }
+
+    fn update_file(&self, s: &str) -> io::Result<String> {
+        let mut doc = s
+            .parse::<DocumentMut>()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        for (i, (name, ..)) in FIELDS.iter().enumerate() {
+            // Through its shortest text, so 0.96
+            // isn't written as 0.9599999785.
+            let value = self.get(i).to_string().parse::<f64>().unwrap_or_default();
+            doc[name] = toml_edit::value(value);
+        }
+        Ok(doc.to_string())
+    }
+
+    fn check(&self) -> io::Result<()> {
+        for (i, &(name, _, min, max, _)) in FIELDS.iter().enumerate() {
+            let value = self.get(i);
+            if !(min..=max).contains(&value) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} must be from {} to {}, got {}", name, min, max, value),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, field: usize) -> f32 {
+        let (_, value, ..) = FIELDS[field];
+        let mut copy = *self;
+        *value(&mut copy)
+    }
+
+    // Steps the field up or down, keeping it
+    // in its range.
+    pub fn adjust(&mut self, field: usize, steps: f32) {
+        let (_, value, min, max, step) = FIELDS[field];
+        let value = value(self);
+        *value = (*value + step * steps).clamp(min, max);
+    }
+}
+
+// F6 shows the values, while shown:
+//   1 to 9   pick one
+//   - =      decrease and increase it
+//   Enter    write them to the tuning file
+#[derive(Debug, Default)]
+pub struct Panel {
+    pub open: bool,
+    selected: usize,
+    status: String,
+}
+
+impl Panel {
+    // Returns whether the file was written, so
+    // it isn't reloaded as an outside change.
+    pub fn update(&mut self, tuning: &mut Tuning, path: &Path) -> bool {
+        if is_key_pressed(KeyCode::F6) {
+            self.open = !self.open;
+        }
+        if !self.open {
+            return false;
+        }
+
+        const KEYS: [KeyCode; FIELDS.len()] = [
+            KeyCode::Key1,
+            KeyCode::Key2,
+            KeyCode::Key3,
+            KeyCode::Key4,
+            KeyCode::Key5,
+            KeyCode::Key6,
+            KeyCode::Key7,
+            KeyCode::Key8,
+            KeyCode::Key9,
+        ];
+        for (field, key) in KEYS.into_iter().enumerate() {
+            if is_key_pressed(key) {
+                self.selected = field;
+            }
+        }
+
+        if is_key_pressed(KeyCode::Minus) {
+            tuning.adjust(self.selected, -1.0);
+        } else if is_key_pressed(KeyCode::Equal) {
+            tuning.adjust(self.selected, 1.0);
+        }
+
+        if is_key_pressed(KeyCode::Enter) {
+            self.status = match tuning.save(path) {
+                Ok(()) => format!("saved {}", path.display()),
+                Err(err) => format!("failed to save: {}", err),
+            };
+            return true;
+        }
+        false
+    }
+
+    pub fn draw(&self, tuning: &Tuning) {
+        if !self.open {
+            return;
+        }
+
+        let x = screen_width() - 260.0;
+        for (i, (name, ..)) in FIELDS.iter().enumerate() {
+            let colour = if i == self.selected { YELLOW } else { WHITE };
+            draw_text(
+                format!("{} {}: {}", i + 1, name, tuning.get(i)).as_str(),
+                x,
+                16.0 + 16.0 * i as f32,
+                16.0,
+                colour,
+            );
+        }
+        draw_text(
+            &self.status,
+            x,
+            16.0 * (FIELDS.len() + 2) as f32,
+            16.0,
+            WHITE,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_matches_the_defaults() {
+        let tuning = Tuning::load("data/tuning.toml").unwrap();
+        assert_eq!(tuning, Tuning::default());
+        assert_eq!(Tuning::parse("setback = 1.74").unwrap().setback, 1.74);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for (s, error) in [
+            ("damping = -1.0", "damping must be from 0 to 1, got -1"),
+            ("damping = 1.5", "damping must be from 0 to 1, got 1.5"),
+            ("setback = 0.0", "setback must be from 0.1 to 10, got 0"),
+            ("max_speed = nan", "max_speed must be from 0 to 16, got NaN"),
+            ("speed = 1.0", "unknown field `speed`"),
+        ] {
+            let err = Tuning::parse(s).expect_err("should fail");
+            assert!(err.to_string().contains(error), "{}", err);
+        }
+    }
+
+    #[test]
+    fn adjusting_keeps_values_in_range() {
+        let mut tuning = Tuning::default();
+        tuning.adjust(4, 2.0);
+        assert_eq!(tuning.waypoint_radius, 60.0);
+        tuning.adjust(0, 1000.0);
+        assert_eq!(tuning.damping, 1.0);
+        tuning.adjust(2, -1000.0);
+        assert_eq!(tuning.setback, 0.1);
+        assert!(Tuning::parse(&toml::to_string(&tuning).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn saving_keeps_the_comments() {
+        let file = read_to_string("data/tuning.toml").unwrap();
+        let mut tuning = Tuning::default();
+        tuning.adjust(0, -2.0);
+
+        let saved = tuning.update_file(&file).unwrap();
+        assert_eq!(saved, file.replace("damping = 0.96\n", "damping = 0.95\n"));
+        assert_eq!(Tuning::parse(&saved).unwrap(), tuning);
+
+        let fresh = tuning.update_file("").unwrap();
+        assert_eq!(Tuning::parse(&fresh).unwrap(), tuning);
+    }
+}